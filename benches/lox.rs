@@ -0,0 +1,127 @@
+// Runs the Lox scripts next to this file through `lox::run` and reports how
+// long each took and how much it allocated. Names given after `--` pick
+// which ones to run, e.g. `cargo bench -- fib zoo`.
+//
+// Scripts print their result on every run, so a wrong answer is easy to spot.
+// Expression statements print their value in Lox, so updates inside loops are
+// written as `var _ = ...` to keep printing out of the timings.
+
+use lox_rust::lox;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Timed runs of each script, after one untimed run to warm up.
+const RUNS: u32 = 5;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// The system allocator, counting every allocation and the bytes asked for.
+// A realloc counts as an allocation of the new size.
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+struct Measurement {
+    best: Duration,
+    mean: Duration,
+    allocations: usize,
+    bytes: usize,
+}
+
+fn measure(name: &str, source: &str) -> Measurement {
+    let run = || {
+        if let Err(e) = lox::run(source.to_string(), lox::Capabilities::none()) {
+            eprintln!("{}: {}", name, e);
+            process::exit(1);
+        }
+    };
+    run();
+
+    let mut best = Duration::MAX;
+    let mut total = Duration::ZERO;
+    let (allocations, bytes) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        BYTES.load(Ordering::Relaxed),
+    );
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        run();
+        let elapsed = start.elapsed();
+        best = best.min(elapsed);
+        total += elapsed;
+    }
+
+    Measurement {
+        best,
+        mean: total / RUNS,
+        allocations: (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / RUNS as usize,
+        bytes: (BYTES.load(Ordering::Relaxed) - bytes) / RUNS as usize,
+    }
+}
+
+fn main() {
+    // Cargo passes `--bench`, anything else is a benchmark name.
+    let names: Vec<String> = env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches");
+    let mut scripts: Vec<_> = fs::read_dir(&dir)
+        .expect("can't read benches/")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "lox"))
+        .collect();
+    scripts.sort();
+
+    let mut results = Vec::new();
+    for path in scripts {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        if !names.is_empty() && !names.contains(&name) {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        results.push((name.clone(), measure(&name, &source)));
+    }
+
+    println!();
+    println!(
+        "{:<16} {:>10} {:>10} {:>12} {:>14}",
+        "benchmark", "best", "mean", "allocations", "bytes"
+    );
+    for (name, m) in results {
+        println!(
+            "{:<16} {:>8.2}ms {:>8.2}ms {:>12} {:>14}",
+            name,
+            m.best.as_secs_f64() * 1000.0,
+            m.mean.as_secs_f64() * 1000.0,
+            m.allocations,
+            m.bytes
+        );
+    }
+}
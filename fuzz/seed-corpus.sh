@@ -0,0 +1,10 @@
+#!/bin/sh
+# Seeds every target's corpus with the test scripts and the known regressions.
+# Run from the repository root, then e.g. `cargo fuzz run parse`.
+set -e
+for target in scan parse format run; do
+    mkdir -p fuzz/corpus/$target
+    for file in tests/lox/*/*.lox tests/snapshots/*.lox fuzz/regressions/*; do
+        cp "$file" "fuzz/corpus/$target/$(echo "$file" | tr / _)"
+    done
+done
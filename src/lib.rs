@@ -0,0 +1,3 @@
+// The interpreter as a library, for the CLI in main.rs, the fuzz targets and
+// the benchmarks.
+pub mod lox;
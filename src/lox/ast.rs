@@ -8,4 +8,42 @@ pub enum Expr<'a> {
     Grouping(Box<Expr<'a>>),
     Unary(Token<'a>, Box<Expr<'a>>),
     Binary(Box<Expr<'a>>, Token<'a>, Box<Expr<'a>>),
+    // Condition, then and else branches of `cond ? a : b`.
+    Conditional(Box<Expr<'a>>, Box<Expr<'a>>, Box<Expr<'a>>),
+    // Variable, `=` or compound assignment operator, value.
+    Assign(Token<'a>, Token<'a>, Box<Expr<'a>>),
+    // Closing `]` and the elements of a list literal.
+    List(Token<'a>, Vec<Expr<'a>>),
+    // Closing `}` and the key-value entries of a map literal.
+    Map(Token<'a>, Vec<(Expr<'a>, Expr<'a>)>),
+    // Object, closing `]` and index of a subscript.
+    Index(Box<Expr<'a>>, Token<'a>, Box<Expr<'a>>),
+    // Object, index, assignment operator and value of `xs[i] = v`.
+    SetIndex(Box<Expr<'a>>, Box<Expr<'a>>, Token<'a>, Box<Expr<'a>>),
+    Get(Box<Expr<'a>>, Token<'a>),
+    // Callee, closing `)` and arguments.
+    Call(Box<Expr<'a>>, Token<'a>, Vec<Expr<'a>>),
+    // String parts and `${...}` expressions, concatenated after converting each to a string.
+    Interpolation(Vec<Expr<'a>>),
+}
+
+impl<'a> Expr<'a> {
+    // Line the expression starts on, as far as the tokens it keeps tell.
+    pub fn line(&self) -> usize {
+        match self {
+            Expr::Identifier(t) | Expr::Literal(t) | Expr::Unary(t, _) | Expr::Assign(t, _, _) => {
+                t.line
+            }
+            Expr::Grouping(e)
+            | Expr::Binary(e, _, _)
+            | Expr::Conditional(e, _, _)
+            | Expr::Index(e, _, _)
+            | Expr::SetIndex(e, _, _, _)
+            | Expr::Get(e, _)
+            | Expr::Call(e, _, _) => e.line(),
+            Expr::List(close, items) => items.first().map_or(close.line, Expr::line),
+            Expr::Map(close, entries) => entries.first().map_or(close.line, |(k, _)| k.line()),
+            Expr::Interpolation(parts) => parts.first().map_or(0, Expr::line),
+        }
+    }
 }
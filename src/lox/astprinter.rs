@@ -1,16 +1,16 @@
 use super::ast::*;
+use super::parser::Stmt;
 use super::tokens::*;
+use super::value::Value;
 
 #[allow(dead_code)]
 pub fn dump_ast(expr: &Expr) -> String {
-    return match expr {
-        Expr::Identifier(t) => {
-            format!("{}", t.lexeme)
-        }
+    match expr {
+        Expr::Identifier(t) => t.lexeme.to_string(),
+        // Strings are printed as written, quotes and escapes included, so the output scans back.
         Expr::Literal(t) => match t.ttype {
-            TokenType::Str => format!("{}", t.literal.as_ref().unwrap()),
             TokenType::Number => format!("{}", t.literal.as_ref().unwrap()),
-            _ => format!("{}", t.lexeme),
+            _ => t.lexeme.to_string(),
         },
         Expr::Grouping(e) => {
             format!("({})", dump_ast(e))
@@ -21,7 +21,248 @@ pub fn dump_ast(expr: &Expr) -> String {
         Expr::Binary(l, t, r) => {
             format!("{}{}{}", dump_ast(l), t.lexeme, dump_ast(r))
         }
-    };
+        Expr::Conditional(c, a, b) => {
+            format!("{}?{}:{}", dump_ast(c), dump_ast(a), dump_ast(b))
+        }
+        Expr::Assign(name, op, value) => {
+            format!("{}{}{}", name.lexeme, op.lexeme, dump_ast(value))
+        }
+        Expr::List(_, items) => format!("[{}]", list(items)),
+        Expr::Map(_, entries) => format!(
+            "{{{}}}",
+            entries
+                .iter()
+                .map(|(k, v)| format!("{}:{}", dump_ast(k), dump_ast(v)))
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+        Expr::Index(obj, _, index) => format!("{}[{}]", dump_ast(obj), dump_ast(index)),
+        Expr::SetIndex(obj, index, op, value) => format!(
+            "{}[{}]{}{}",
+            dump_ast(obj),
+            dump_ast(index),
+            op.lexeme,
+            dump_ast(value)
+        ),
+        Expr::Get(obj, name) => format!("{}.{}", dump_ast(obj), name.lexeme),
+        Expr::Call(callee, _, args) => format!("{}({})", dump_ast(callee), list(args)),
+        Expr::Interpolation(parts) => interpolation(parts, dump_ast),
+    }
+}
+
+fn list(items: &[Expr]) -> String {
+    items
+        .iter()
+        .map(dump_ast)
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Prints string parts as written, with `${`/`}` kept in their lexemes.
+pub fn interpolation(parts: &[Expr], print: impl Fn(&Expr) -> String) -> String {
+    parts
+        .iter()
+        .map(|p| match p {
+            Expr::Literal(t)
+                if t.ttype == TokenType::Interpolation || t.ttype == TokenType::Str =>
+            {
+                t.lexeme.to_string()
+            }
+            _ => print(p),
+        })
+        .collect()
+}
+
+// One node per line, children indented below their parent, e.g.
+//
+//   Expression
+//     Binary +
+//       Literal 1
+//       Identifier x
+#[allow(dead_code)]
+pub fn dump_tree(stmts: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in stmts {
+        stmt_tree(&mut out, 0, stmt);
+    }
+    out
+}
+
+fn node(out: &mut String, depth: usize, label: &str) {
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(label);
+    out.push('\n');
+}
+
+fn stmt_tree(out: &mut String, depth: usize, stmt: &Stmt) {
+    let child = depth + 1;
+    match stmt {
+        Stmt::Expression(e) => {
+            node(out, depth, "Expression");
+            expr_tree(out, child, e);
+        }
+        Stmt::Var(name, init) => {
+            node(out, depth, &format!("Var {}", name.lexeme));
+            if let Some(init) = init {
+                expr_tree(out, child, init);
+            }
+        }
+        Stmt::Block(_, stmts, _) => {
+            node(out, depth, "Block");
+            for s in stmts {
+                stmt_tree(out, child, s);
+            }
+        }
+        Stmt::If(_, cond, then, otherwise) => {
+            node(out, depth, "If");
+            expr_tree(out, child, cond);
+            stmt_tree(out, child, then);
+            if let Some(otherwise) = otherwise {
+                node(out, child, "Else");
+                stmt_tree(out, child + 1, otherwise);
+            }
+        }
+        Stmt::While(_, cond, body) => {
+            node(out, depth, "While");
+            expr_tree(out, child, cond);
+            stmt_tree(out, child, body);
+        }
+        // The clauses are optional, so each is labelled.
+        Stmt::For(_, init, cond, incr, body) => {
+            node(out, depth, "For");
+            if let Some(init) = init {
+                node(out, child, "Initializer");
+                stmt_tree(out, child + 1, init);
+            }
+            if let Some(cond) = cond {
+                node(out, child, "Condition");
+                expr_tree(out, child + 1, cond);
+            }
+            if let Some(incr) = incr {
+                node(out, child, "Increment");
+                expr_tree(out, child + 1, incr);
+            }
+            stmt_tree(out, child, body);
+        }
+        Stmt::ForIn(_, name, iterable, body) => {
+            node(out, depth, &format!("ForIn {}", name.lexeme));
+            expr_tree(out, child, iterable);
+            stmt_tree(out, child, body);
+        }
+        Stmt::Break(_) => node(out, depth, "Break"),
+        Stmt::Continue(_) => node(out, depth, "Continue"),
+        Stmt::Throw(_, value) => {
+            node(out, depth, "Throw");
+            expr_tree(out, child, value);
+        }
+        Stmt::Import(_, path, alias, names) => {
+            let mut label = format!("Import {}", path.lexeme);
+            if let Some(alias) = alias {
+                label.push_str(&format!(" as {}", alias.lexeme));
+            }
+            node(out, depth, &label);
+            for (name, local) in names {
+                node(
+                    out,
+                    child,
+                    &format!("Name {} as {}", name.lexeme, local.lexeme),
+                );
+            }
+        }
+        Stmt::Export(_, var) => {
+            node(out, depth, "Export");
+            stmt_tree(out, child, var);
+        }
+        Stmt::Try(_, body, catch, finally) => {
+            node(out, depth, "Try");
+            stmt_tree(out, child, body);
+            if let Some((name, handler)) = catch {
+                node(out, child, &format!("Catch {}", name.lexeme));
+                stmt_tree(out, child + 1, handler);
+            }
+            if let Some(finally) = finally {
+                node(out, child, "Finally");
+                stmt_tree(out, child + 1, finally);
+            }
+        }
+    }
+}
+
+fn expr_tree(out: &mut String, depth: usize, expr: &Expr) {
+    let child = depth + 1;
+    match expr {
+        Expr::Identifier(t) => node(out, depth, &format!("Identifier {}", t.lexeme)),
+        // Keywords like `true` have no literal value.
+        Expr::Literal(t) => {
+            let value = t.literal.as_ref().map_or(t.lexeme.to_string(), Value::repr);
+            node(out, depth, &format!("Literal {}", value));
+        }
+        Expr::Grouping(e) => {
+            node(out, depth, "Grouping");
+            expr_tree(out, child, e);
+        }
+        Expr::Unary(op, e) => {
+            node(out, depth, &format!("Unary {}", op.lexeme));
+            expr_tree(out, child, e);
+        }
+        Expr::Binary(l, op, r) => {
+            node(out, depth, &format!("Binary {}", op.lexeme));
+            expr_tree(out, child, l);
+            expr_tree(out, child, r);
+        }
+        Expr::Conditional(c, a, b) => {
+            node(out, depth, "Conditional");
+            for e in &[c, a, b] {
+                expr_tree(out, child, e);
+            }
+        }
+        Expr::Assign(name, op, value) => {
+            node(out, depth, &format!("Assign {} {}", name.lexeme, op.lexeme));
+            expr_tree(out, child, value);
+        }
+        Expr::List(_, items) => {
+            node(out, depth, "List");
+            for item in items {
+                expr_tree(out, child, item);
+            }
+        }
+        Expr::Map(_, entries) => {
+            node(out, depth, "Map");
+            for (k, v) in entries {
+                node(out, child, "Entry");
+                expr_tree(out, child + 1, k);
+                expr_tree(out, child + 1, v);
+            }
+        }
+        Expr::Index(obj, _, index) => {
+            node(out, depth, "Index");
+            expr_tree(out, child, obj);
+            expr_tree(out, child, index);
+        }
+        Expr::SetIndex(obj, index, op, value) => {
+            node(out, depth, &format!("SetIndex {}", op.lexeme));
+            expr_tree(out, child, obj);
+            expr_tree(out, child, index);
+            expr_tree(out, child, value);
+        }
+        Expr::Get(obj, name) => {
+            node(out, depth, &format!("Get {}", name.lexeme));
+            expr_tree(out, child, obj);
+        }
+        Expr::Call(callee, _, args) => {
+            node(out, depth, "Call");
+            expr_tree(out, child, callee);
+            for arg in args {
+                expr_tree(out, child, arg);
+            }
+        }
+        Expr::Interpolation(parts) => {
+            node(out, depth, "Interpolation");
+            for part in parts {
+                expr_tree(out, child, part);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -39,12 +280,14 @@ mod tests {
                 lexeme: "!",
                 literal: None,
                 line: 1,
+                trivia: Vec::new(),
             },
             Box::new(Expr::Literal(Token {
                 ttype: Number,
-                literal: Some(Literal::Number(0.)),
+                literal: Some(Value::Number(0.)),
                 lexeme: "0.",
                 line: 1,
+                trivia: Vec::new(),
             })),
         );
         let s = dump_ast(&e);
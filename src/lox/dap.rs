@@ -0,0 +1,649 @@
+// A Debug Adapter Protocol server, run by `lox-rust dap` over stdio. It
+// launches one script and runs it on this thread, with the interpreter's
+// debugger hook stopping it at breakpoints and steps. Messages are read on
+// another thread, so a running script can still be paused.
+
+use super::interpreter::{Debugger, Interpreter};
+use super::protocol::{self, as_str, as_usize, get, int, object, string};
+use super::stdlib::Capabilities;
+use super::value::Value;
+use super::{errors::CompileError, errors::CompileErrorType, execute, RunError};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// Scripts have a single thread, which is reported with this id.
+const THREAD: usize = 1;
+
+// Serves one debugging session from `input`, until the client disconnects.
+pub fn serve(input: impl BufRead + Send + 'static, output: impl Write + 'static) -> io::Result<()> {
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(body)) = protocol::read_body(&mut input) {
+            if sender.send(body).is_err() {
+                break;
+            }
+        }
+    });
+
+    let connection = Rc::new(RefCell::new(Connection {
+        output: Box::new(output),
+        seq: 0,
+    }));
+    let session = Rc::new(RefCell::new(Session {
+        connection: connection.clone(),
+        messages,
+        program: None,
+        stop_on_entry: false,
+        breakpoints: HashMap::new(),
+        step: Step::Run,
+        lines: Vec::new(),
+        handles: Vec::new(),
+        disconnected: false,
+    }));
+
+    // Breakpoints and the like are set up before the script starts.
+    loop {
+        let control = match session.borrow_mut().next_request(None)? {
+            Some(control) => control,
+            None => return Ok(()),
+        };
+        match control {
+            Control::Start => break,
+            Control::Disconnect => return Ok(()),
+            Control::Stay | Control::Resume => {}
+        }
+    }
+
+    let program = session.borrow().program.clone();
+    let exit_code = match program {
+        Some(program) => run(&session, &connection, &program)?,
+        None => None,
+    };
+
+    if session.borrow().disconnected {
+        return Ok(());
+    }
+    let mut c = connection.borrow_mut();
+    if let Some(code) = exit_code {
+        c.event(
+            "exited",
+            object(vec![("exitCode", Value::Int(code.into()))]),
+        )?;
+    }
+    c.event("terminated", object(Vec::new()))?;
+    drop(c);
+
+    // The client may still ask for things until it disconnects.
+    while let Some(control) = session.borrow_mut().next_request(None)? {
+        if let Control::Disconnect = control {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Runs the script and returns its exit code, None if the client stopped it.
+fn run(
+    session: &Rc<RefCell<Session>>,
+    connection: &Rc<RefCell<Connection>>,
+    program: &Path,
+) -> io::Result<Option<i32>> {
+    if session.borrow().stop_on_entry {
+        session.borrow_mut().step = Step::Next("entry");
+    }
+
+    // Standard input and output carry the protocol, so scripts can't use them.
+    let mut interpreter = Interpreter::with_capabilities(Capabilities {
+        process: false,
+        ..Capabilities::all()
+    });
+    interpreter.set_file(program);
+    interpreter.set_output(Box::new(LineWriter::new(Output(connection.clone()))));
+    interpreter.set_debugger(Box::new(Hook(session.clone())));
+
+    let result = fs::read_to_string(program)
+        .map_err(|e| RunError::Io(format!("{}: {}", program.display(), e)))
+        .and_then(|source| execute(&mut interpreter, &source));
+    // Drops the output, writing out a last line without a newline.
+    interpreter.set_output(Box::new(io::sink()));
+
+    match result {
+        Ok(()) => Ok(Some(0)),
+        Err(_) if session.borrow().disconnected => Ok(None),
+        Err(e) => {
+            let output = object(vec![
+                ("category", string("stderr")),
+                ("output", string(&format!("{}\n", e))),
+            ]);
+            connection.borrow_mut().event("output", output)?;
+            Ok(Some(e.exit_code()))
+        }
+    }
+}
+
+// The client's end, to which responses and events are sent in order.
+struct Connection {
+    output: Box<dyn Write>,
+    seq: usize,
+}
+
+impl Connection {
+    fn send(&mut self, mut fields: Vec<(&str, Value)>) -> io::Result<()> {
+        self.seq += 1;
+        fields.insert(0, ("seq", int(self.seq)));
+        protocol::write_message(&mut self.output, &object(fields))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(vec![
+            ("type", string("event")),
+            ("event", string(event)),
+            ("body", body),
+        ])
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut fields = vec![
+            ("type", string("response")),
+            ("request_seq", get(request, "seq")),
+            ("success", Value::Bool(result.is_ok())),
+            ("command", get(request, "command")),
+        ];
+        match result {
+            Ok(body) => fields.push(("body", body)),
+            Err(message) => fields.push(("message", string(&message))),
+        }
+        self.send(fields)
+    }
+}
+
+// What the script prints, sent as `output` events.
+struct Output(Rc<RefCell<Connection>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let output = object(vec![
+            ("category", string("stdout")),
+            ("output", string(&String::from_utf8_lossy(buf))),
+        ]);
+        self.0.borrow_mut().event("output", output)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Where to stop next, besides at breakpoints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Run,
+    // At the next statement, giving this as the reason.
+    Next(&'static str),
+    // At the next statement no deeper than the depth given.
+    Over(usize),
+    // At the next statement less deep than the depth given.
+    Out(usize),
+}
+
+impl Step {
+    fn stops_at(self, depth: usize) -> Option<&'static str> {
+        match self {
+            Step::Run => None,
+            Step::Next(reason) => Some(reason),
+            Step::Over(from) if depth <= from => Some("step"),
+            Step::Out(from) if depth < from => Some("step"),
+            Step::Over(_) | Step::Out(_) => None,
+        }
+    }
+}
+
+// What a `variablesReference` stands for while the script is paused.
+enum Handle {
+    // The block scopes or the globals of the file at a depth.
+    Locals(usize),
+    Globals(usize),
+    // The elements of a list or map, or the exports of a module.
+    Value(Value),
+}
+
+// What handling a request means for the script.
+enum Control {
+    Stay,
+    Resume,
+    // The client is done setting up and the script can start.
+    Start,
+    Disconnect,
+}
+
+struct Session {
+    connection: Rc<RefCell<Connection>>,
+    messages: Receiver<String>,
+    program: Option<PathBuf>,
+    stop_on_entry: bool,
+    // Lines with breakpoints by file.
+    breakpoints: HashMap<PathBuf, HashSet<usize>>,
+    step: Step,
+    // Line of the statement running at each depth, outermost first.
+    lines: Vec<usize>,
+    // Numbered from 1 by `variablesReference`, and forgotten when the script resumes.
+    handles: Vec<Handle>,
+    disconnected: bool,
+}
+
+struct Hook(Rc<RefCell<Session>>);
+
+impl Debugger for Hook {
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        depth: usize,
+        line: usize,
+    ) -> Result<(), CompileError> {
+        match self
+            .0
+            .borrow_mut()
+            .before_statement(interpreter, depth, line)
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(CompileError {
+                err: CompileErrorType::Interrupted,
+                line,
+                text: Some("Stopped by the debugger".to_string()),
+                at: None,
+            }),
+            Err(e) => {
+                self.0.borrow_mut().disconnected = true;
+                Err(CompileError {
+                    err: CompileErrorType::Interrupted,
+                    line,
+                    text: Some(format!("Lost the debugger: {}", e)),
+                    at: None,
+                })
+            }
+        }
+    }
+}
+
+impl Session {
+    // Stops at the statement if there's a reason to, and returns whether the
+    // script should go on.
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        depth: usize,
+        line: usize,
+    ) -> io::Result<bool> {
+        // Finally blocks still run after the client has gone, but stop at once.
+        if self.disconnected {
+            return Ok(false);
+        }
+        self.lines.truncate(depth);
+        self.lines.resize(depth + 1, line);
+
+        // Requests sent while the script was running.
+        loop {
+            match self.messages.try_recv() {
+                Ok(body) => {
+                    if let Control::Disconnect = self.handle(&protocol::parse(&body)?, None)? {
+                        return Ok(false);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    return Ok(false);
+                }
+            }
+        }
+
+        let at_breakpoint = interpreter
+            .file_at(depth)
+            .and_then(|file| self.breakpoints.get(file))
+            .is_some_and(|lines| lines.contains(&line));
+        let reason = match self.step.stops_at(depth) {
+            Some(reason) => reason,
+            None if at_breakpoint => "breakpoint",
+            None => return Ok(true),
+        };
+
+        self.step = Step::Run;
+        self.connection.borrow_mut().event(
+            "stopped",
+            object(vec![
+                ("reason", string(reason)),
+                ("threadId", int(THREAD)),
+                ("allThreadsStopped", Value::Bool(true)),
+            ]),
+        )?;
+        loop {
+            match self.next_request(Some(interpreter))? {
+                Some(Control::Resume) => {
+                    self.handles.clear();
+                    return Ok(true);
+                }
+                Some(Control::Disconnect) | None => return Ok(false),
+                Some(Control::Stay) | Some(Control::Start) => {}
+            }
+        }
+    }
+
+    // Waits for the next request and handles it, None once the client is gone.
+    fn next_request(
+        &mut self,
+        interpreter: Option<&mut Interpreter>,
+    ) -> io::Result<Option<Control>> {
+        match self.messages.recv() {
+            Ok(body) => self.handle(&protocol::parse(&body)?, interpreter).map(Some),
+            Err(_) => {
+                self.disconnected = true;
+                Ok(None)
+            }
+        }
+    }
+
+    // Handles a request. The interpreter is only there while the script is paused.
+    fn handle(
+        &mut self,
+        request: &Value,
+        interpreter: Option<&mut Interpreter>,
+    ) -> io::Result<Control> {
+        let command = get(request, "command");
+        let arguments = get(request, "arguments");
+        let depth = self.lines.len().saturating_sub(1);
+
+        let mut control = Control::Stay;
+        let result = match as_str(&command).unwrap_or("") {
+            "initialize" => Ok(object(vec![
+                ("supportsConfigurationDoneRequest", Value::Bool(true)),
+                ("supportsEvaluateForHovers", Value::Bool(true)),
+                ("supportsTerminateRequest", Value::Bool(true)),
+            ])),
+            "launch" => match as_str(&get(&arguments, "program")) {
+                Some(program) => {
+                    self.program = Some(PathBuf::from(program));
+                    self.stop_on_entry = get(&arguments, "stopOnEntry").is_truthy();
+                    Ok(Value::Nil)
+                }
+                None => Err("launch needs the program to debug".to_string()),
+            },
+            "setBreakpoints" => Ok(self.set_breakpoints(&arguments)),
+            "configurationDone" => {
+                control = Control::Start;
+                Ok(Value::Nil)
+            }
+            "threads" => Ok(object(vec![(
+                "threads",
+                Value::list(vec![object(vec![
+                    ("id", int(THREAD)),
+                    ("name", string("main")),
+                ])]),
+            )])),
+            "pause" => {
+                self.step = Step::Next("pause");
+                Ok(Value::Nil)
+            }
+            "continue" | "next" | "stepIn" | "stepOut" if interpreter.is_some() => {
+                self.step = match as_str(&command) {
+                    Some("next") => Step::Over(depth),
+                    Some("stepIn") => Step::Next("step"),
+                    Some("stepOut") => Step::Out(depth),
+                    _ => Step::Run,
+                };
+                control = Control::Resume;
+                Ok(Value::Nil)
+            }
+            "stackTrace" | "scopes" | "variables" | "evaluate" | "continue" | "next" | "stepIn"
+            | "stepOut"
+                if interpreter.is_none() =>
+            {
+                Err("The script isn't paused".to_string())
+            }
+            "stackTrace" => Ok(self.stack_trace(interpreter.as_deref())),
+            "scopes" => self.scopes(&arguments),
+            "variables" => self.variables(&arguments, interpreter.as_deref()),
+            "evaluate" => self.evaluate(&arguments, depth, interpreter),
+            "disconnect" | "terminate" => {
+                self.disconnected = true;
+                control = Control::Disconnect;
+                Ok(Value::Nil)
+            }
+            command => Err(format!("Unknown command '{}'", command)),
+        };
+
+        let mut connection = self.connection.borrow_mut();
+        connection.respond(request, result)?;
+        if as_str(&command) == Some("initialize") {
+            connection.event("initialized", object(Vec::new()))?;
+        }
+        Ok(control)
+    }
+
+    // Replaces the breakpoints of one file. Any line is accepted.
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = get(&get(arguments, "source"), "path");
+        let path = as_str(&path).unwrap_or("");
+        let file = Path::new(path)
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(path));
+        let lines: Vec<usize> = match &get(arguments, "breakpoints") {
+            Value::List(breakpoints) => breakpoints
+                .borrow()
+                .iter()
+                .filter_map(|b| as_usize(&get(b, "line")))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let verified = lines
+            .iter()
+            .map(|&line| object(vec![("verified", Value::Bool(true)), ("line", int(line))]))
+            .collect();
+        self.breakpoints.insert(file, lines.into_iter().collect());
+        object(vec![("breakpoints", Value::list(verified))])
+    }
+
+    // One frame per file running, the innermost first. Frame ids are depths plus one.
+    fn stack_trace(&self, interpreter: Option<&Interpreter>) -> Value {
+        let frames = (0..self.lines.len())
+            .rev()
+            .map(|depth| {
+                let file = interpreter.and_then(|i| i.file_at(depth));
+                let name = file
+                    .and_then(Path::file_name)
+                    .map_or("script".to_string(), |n| n.to_string_lossy().to_string());
+                let mut frame = vec![
+                    ("id", int(depth + 1)),
+                    ("name", string(&name)),
+                    ("line", int(self.lines[depth])),
+                    ("column", int(1)),
+                ];
+                if let Some(file) = file {
+                    let source = object(vec![
+                        ("name", string(&name)),
+                        ("path", string(&file.display().to_string())),
+                    ]);
+                    frame.push(("source", source));
+                }
+                object(frame)
+            })
+            .collect::<Vec<_>>();
+        object(vec![
+            ("totalFrames", int(frames.len())),
+            ("stackFrames", Value::list(frames)),
+        ])
+    }
+
+    fn frame_depth(&self, arguments: &Value) -> Result<usize, String> {
+        match as_usize(&get(arguments, "frameId")) {
+            Some(id) if (1..=self.lines.len()).contains(&id) => Ok(id - 1),
+            _ => Err("Unknown frame".to_string()),
+        }
+    }
+
+    fn scopes(&mut self, arguments: &Value) -> Result<Value, String> {
+        let depth = self.frame_depth(arguments)?;
+        let scope = |name: &str, reference: usize| {
+            object(vec![
+                ("name", string(name)),
+                ("variablesReference", int(reference)),
+                ("expensive", Value::Bool(false)),
+            ])
+        };
+        let locals = scope("Locals", self.handle_for(Handle::Locals(depth)));
+        let globals = scope("Globals", self.handle_for(Handle::Globals(depth)));
+        Ok(object(vec![("scopes", Value::list(vec![locals, globals]))]))
+    }
+
+    fn handle_for(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    fn variables(
+        &mut self,
+        arguments: &Value,
+        interpreter: Option<&Interpreter>,
+    ) -> Result<Value, String> {
+        let handle = as_usize(&get(arguments, "variablesReference"))
+            .and_then(|reference| reference.checked_sub(1))
+            .and_then(|i| self.handles.get(i));
+        let variables: Vec<(String, Value)> = match (handle, interpreter) {
+            (Some(Handle::Locals(depth)), Some(interpreter)) => {
+                // Inner scopes shadow outer ones.
+                let mut seen = HashSet::new();
+                let mut variables: Vec<_> = interpreter.scopes_at(*depth)[1..]
+                    .iter()
+                    .rev()
+                    .flat_map(|scope| scope.iter())
+                    .filter(|(name, _)| seen.insert(name.to_string()))
+                    .map(|(name, v)| (name.clone(), v.clone()))
+                    .collect();
+                variables.sort_by(|a, b| a.0.cmp(&b.0));
+                variables
+            }
+            // Built-in functions are left out.
+            (Some(Handle::Globals(depth)), Some(interpreter)) => {
+                let mut variables: Vec<_> = interpreter.scopes_at(*depth)[0]
+                    .iter()
+                    .filter(|(name, v)| !matches!(v, Value::Native(f) if f.name == **name))
+                    .map(|(name, v)| (name.clone(), v.clone()))
+                    .collect();
+                variables.sort_by(|a, b| a.0.cmp(&b.0));
+                variables
+            }
+            (Some(Handle::Value(v)), _) => children(v),
+            _ => return Err("Unknown variables reference".to_string()),
+        };
+
+        let variables = variables
+            .into_iter()
+            .map(|(name, v)| {
+                object(vec![
+                    ("name", string(&name)),
+                    ("value", string(&v.repr())),
+                    ("type", string(v.type_name())),
+                    ("variablesReference", int(self.reference(&v))),
+                ])
+            })
+            .collect();
+        Ok(object(vec![("variables", Value::list(variables))]))
+    }
+
+    // A handle for the children of values that have any, otherwise 0.
+    fn reference(&mut self, v: &Value) -> usize {
+        match v {
+            Value::List(_) | Value::Map(_) | Value::Module(_) => {
+                self.handle_for(Handle::Value(v.clone()))
+            }
+            _ => 0,
+        }
+    }
+
+    // Evaluates in the frame given, or the innermost one.
+    fn evaluate(
+        &mut self,
+        arguments: &Value,
+        depth: usize,
+        interpreter: Option<&mut Interpreter>,
+    ) -> Result<Value, String> {
+        let depth = match get(arguments, "frameId") {
+            Value::Nil => depth,
+            _ => self.frame_depth(arguments)?,
+        };
+        let expression = as_str(&get(arguments, "expression"))
+            .unwrap_or("")
+            .to_string();
+        let interpreter = interpreter.ok_or("The script isn't paused")?;
+        match interpreter.evaluate_at(depth, &expression) {
+            Ok(v) => Ok(object(vec![
+                ("result", string(&v.repr())),
+                ("type", string(v.type_name())),
+                ("variablesReference", int(self.reference(&v))),
+            ])),
+            Err(e) => Err(e.message()),
+        }
+    }
+}
+
+// Names and values inside a list, map or module.
+fn children(v: &Value) -> Vec<(String, Value)> {
+    match v {
+        Value::List(items) => items
+            .borrow()
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (i.to_string(), item.clone()))
+            .collect(),
+        Value::Map(entries) => entries
+            .borrow()
+            .iter()
+            .map(|(k, v)| (k.repr(), v.clone()))
+            .collect(),
+        Value::Module(module) => {
+            let mut exports: Vec<_> = module
+                .exports
+                .iter()
+                .map(|(name, v)| (name.clone(), v.clone()))
+                .collect();
+            exports.sort_by(|a, b| a.0.cmp(&b.0));
+            exports
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        assert_eq!(Step::Run.stops_at(0), None);
+        assert_eq!(Step::Next("pause").stops_at(3), Some("pause"));
+        assert_eq!(Step::Over(1).stops_at(2), None);
+        assert_eq!(Step::Over(1).stops_at(1), Some("step"));
+        assert_eq!(Step::Over(1).stops_at(0), Some("step"));
+        assert_eq!(Step::Out(1).stops_at(1), None);
+        assert_eq!(Step::Out(1).stops_at(0), Some("step"));
+    }
+
+    #[test]
+    fn children_of_values() {
+        let list = Value::list(vec![Value::Int(1), Value::Str("a".to_string())]);
+        let names: Vec<String> = children(&list)
+            .iter()
+            .map(|(name, v)| format!("{}={}", name, v.repr()))
+            .collect();
+        assert_eq!(names, ["0=1", "1=\"a\""]);
+        assert!(children(&Value::Int(1)).is_empty());
+    }
+}
@@ -1,11 +1,19 @@
+use super::value::Value;
 use std::{error, fmt};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum CompileErrorType {
     UnexpectedChar(char),
     UnterminatedString,
+    UnterminatedComment,
+    InvalidEscape,
+    InvalidNumber,
     InvalidSyntax,
-    EvaluationFailed
+    EvaluationFailed,
+    // A value raised by `throw` that no `catch` handled yet.
+    Thrown(Value),
+    // Stops the script from outside, e.g. by a debugger. `catch` doesn't handle it.
+    Interrupted,
 }
 
 #[derive(Debug, PartialEq)]
@@ -13,16 +21,63 @@ pub struct CompileError {
     pub err: CompileErrorType,
     pub line: usize,
     pub text: Option<String>,
+    // The token a syntax error was found at, like `';'` or `end`.
+    pub at: Option<String>,
 }
 
 impl error::Error for CompileError {}
 
+// Compile errors read `[line N] Error: message`, runtime errors put the
+// line after the message, like the reference implementation.
 impl fmt::Display for CompileError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[line {}] Error", self.line)?;
-        if let Some(t) = &self.text {
-            write!(f, " at '{}'", t)?;
+        match &self.err {
+            CompileErrorType::Thrown(v) => {
+                write!(f, "{}\n[line {}]", self.message(), self.line)?;
+                if let Value::Error(e) = v {
+                    write!(f, "\n    raised at line {}", e.trace[0])?;
+                    for line in &e.trace[1..] {
+                        write!(f, "\n    rethrown at line {}", line)?;
+                    }
+                }
+                Ok(())
+            }
+            CompileErrorType::EvaluationFailed | CompileErrorType::Interrupted => {
+                write!(f, "{}\n[line {}]", self.message(), self.line)
+            }
+            _ => {
+                write!(f, "[line {}] Error", self.line)?;
+                if let Some(at) = &self.at {
+                    write!(f, " at {}", at)?;
+                }
+                write!(f, ": {}", self.message())
+            }
         }
-        write!(f, ": {:?}", self.err)
+    }
+}
+
+impl CompileError {
+    // What went wrong, without the line.
+    pub fn message(&self) -> String {
+        let text = self.text.as_deref().unwrap_or("");
+        match &self.err {
+            CompileErrorType::UnexpectedChar(c) => format!("Unexpected character {:?}.", c),
+            CompileErrorType::UnterminatedString => "Unterminated string.".to_string(),
+            CompileErrorType::UnterminatedComment => "Unterminated comment.".to_string(),
+            CompileErrorType::InvalidEscape => format!("Invalid escape sequence '{}'.", text),
+            CompileErrorType::InvalidNumber => format!("Invalid number '{}'.", text),
+            CompileErrorType::Thrown(v) => format!("Uncaught {}", v.repr()),
+            _ => text.to_string(),
+        }
+    }
+
+    // Whether the error happened while running rather than reading the source.
+    pub fn is_runtime(&self) -> bool {
+        matches!(
+            self.err,
+            CompileErrorType::EvaluationFailed
+                | CompileErrorType::Thrown(_)
+                | CompileErrorType::Interrupted
+        )
     }
 }
@@ -0,0 +1,684 @@
+use super::ast::*;
+use super::astprinter;
+use super::errors::CompileError;
+use super::parser::{Parser, Stmt};
+use super::scanner::Scanner;
+use super::tokens::*;
+
+use TokenType::*;
+
+pub const DEFAULT_WIDTH: usize = 80;
+const INDENT: usize = 4;
+
+pub fn format_source(source: &str, width: usize) -> Result<String, CompileError> {
+    let mut s = Scanner::lossless(source);
+    let tokens = s.scan_tokens()?;
+
+    let mut p = Parser::new(tokens);
+    let stmts = p.parse()?;
+
+    let comments: Vec<&Trivia> = tokens
+        .iter()
+        .flat_map(|t| &t.trivia)
+        .filter(|t| t.kind == TriviaKind::Comment)
+        .collect();
+
+    let mut f = Formatter {
+        width,
+        comments: &comments,
+        next_comment: 0,
+        last_line: 0,
+        out: String::new(),
+    };
+
+    f.stmts(stmts, 0, usize::MAX);
+    f.flush_comments(usize::MAX, 0);
+
+    Ok(f.out)
+}
+
+struct Formatter<'a> {
+    width: usize,
+    comments: &'a [&'a Trivia<'a>],
+    next_comment: usize,
+    // Last source line that was written out, used to keep blank lines between items.
+    last_line: usize,
+    out: String,
+}
+
+impl<'a> Formatter<'a> {
+    // `end` is the line of the `}` after the statements, which takes the comments on its line.
+    fn stmts(&mut self, stmts: &[Stmt], indent: usize, end: usize) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            let last = self.stmt(stmt, indent);
+
+            // A comment at the end of a line belongs to the last statement on it.
+            let next_line = stmts.get(i + 1).map_or(end, stmt_first_line);
+            if next_line != last {
+                self.trailing_comment(last);
+            }
+        }
+    }
+
+    // Returns the last source line of the statement.
+    fn stmt(&mut self, stmt: &Stmt, indent: usize) -> usize {
+        let (first, last) = (stmt_first_line(stmt), stmt_last_line(stmt));
+
+        // Comments inside a simple multi-line statement are moved above it,
+        // statements with a body keep them there.
+        match stmt {
+            Stmt::Expression(_)
+            | Stmt::Var(..)
+            | Stmt::Export(..)
+            | Stmt::Import(..)
+            | Stmt::Throw(..) => self.flush_comments(last, indent),
+            _ => self.flush_comments(first, indent),
+        }
+        self.blank_line(first);
+
+        let text = match stmt {
+            Stmt::Expression(e) => format!("{};", self.expr(e, indent, indent)),
+            Stmt::Var(name, init) => self.var("var", name, init.as_deref(), indent),
+            Stmt::Export(_, var) => match &**var {
+                Stmt::Var(name, init) => self.var("export var", name, init.as_deref(), indent),
+                _ => unreachable!("only variables are exported"),
+            },
+            Stmt::Import(_, path, alias, names) => {
+                let mut text = "import ".to_string();
+                if !names.is_empty() {
+                    let names: Vec<String> = names
+                        .iter()
+                        .map(|(name, local)| match local.lexeme {
+                            l if l == name.lexeme => l.to_string(),
+                            l => format!("{} as {}", name.lexeme, l),
+                        })
+                        .collect();
+                    text.push_str(&format!("{{ {} }} from ", names.join(", ")));
+                }
+                text.push_str(path.lexeme);
+                if let Some(alias) = alias {
+                    text.push_str(" as ");
+                    text.push_str(alias.lexeme);
+                }
+                text.push(';');
+                text
+            }
+            Stmt::Throw(_, e) => format!("throw {};", self.expr(e, indent + 6, indent)),
+            Stmt::Break(_) => "break;".to_string(),
+            Stmt::Continue(_) => "continue;".to_string(),
+            Stmt::Block(open, stmts, close) => {
+                return self.block(String::new(), open, stmts, close, indent)
+            }
+            Stmt::If(_, cond, then, otherwise) => {
+                return self.if_stmt(String::new(), cond, then, otherwise.as_deref(), indent)
+            }
+            Stmt::While(_, cond, body) => {
+                let header = format!("while ({}) ", self.expr(cond, indent + 7, indent));
+                return self.body(header, body, indent);
+            }
+            Stmt::For(_, init, cond, incr, body) => {
+                let mut header = "for (".to_string();
+                if let Some(init) = init {
+                    header.push_str(&for_initializer(init));
+                }
+                header.push(';');
+                if let Some(cond) = cond {
+                    header.push(' ');
+                    header.push_str(&flat(cond));
+                }
+                header.push(';');
+                if let Some(incr) = incr {
+                    header.push(' ');
+                    header.push_str(&flat(incr));
+                }
+                header.push_str(") ");
+                return self.body(header, body, indent);
+            }
+            Stmt::ForIn(_, name, iterable, body) => {
+                let header = format!("for (var {} in {}) ", name.lexeme, flat(iterable));
+                return self.body(header, body, indent);
+            }
+            Stmt::Try(_, body, catch, finally) => {
+                let mut last = self.body("try ".to_string(), body, indent);
+                if let Some((name, handler)) = catch {
+                    let header = format!("{} catch ({}) ", self.take_last_line(), name.lexeme);
+                    last = self.body(header, handler, indent);
+                }
+                if let Some(finally) = finally {
+                    let header = format!("{} finally ", self.take_last_line());
+                    last = self.body(header, finally, indent);
+                }
+                return last;
+            }
+        };
+        self.line(indent, &text);
+        self.last_line = last;
+        last
+    }
+
+    fn var(&self, keyword: &str, name: &Token, init: Option<&Expr>, indent: usize) -> String {
+        match init {
+            None => format!("{} {};", keyword, name.lexeme),
+            Some(e) => {
+                let prefix = format!("{} {} = ", keyword, name.lexeme);
+                let value = self.expr(e, indent + prefix.len(), indent);
+                format!("{}{};", prefix, value)
+            }
+        }
+    }
+
+    // `else` goes on the line that closes the block before it, `else if` chains stay flat.
+    fn if_stmt(
+        &mut self,
+        prefix: String,
+        cond: &Expr,
+        then: &Stmt,
+        otherwise: Option<&Stmt>,
+        indent: usize,
+    ) -> usize {
+        let (mut prefix, mut cond, mut then, mut otherwise) = (prefix, cond, then, otherwise);
+        loop {
+            let column = indent + prefix.chars().count() + 4;
+            let header = format!("{}if ({}) ", prefix, self.expr(cond, column, indent));
+            let last = self.body(header, then, indent);
+
+            let branch = match otherwise {
+                Some(branch) => branch,
+                None => return last,
+            };
+            prefix = match then {
+                Stmt::Block(..) => format!("{} else ", self.take_last_line()),
+                _ => "else ".to_string(),
+            };
+            match branch {
+                Stmt::If(_, c, t, o) => {
+                    cond = c;
+                    then = t;
+                    otherwise = o.as_deref();
+                }
+                _ => return self.body(prefix, branch, indent),
+            }
+        }
+    }
+
+    // Blocks start on the line of their header, other statements go on the next line.
+    fn body(&mut self, header: String, body: &Stmt, indent: usize) -> usize {
+        match body {
+            Stmt::Block(open, stmts, close) => self.block(header, open, stmts, close, indent),
+            _ => {
+                self.line(indent, header.trim_end());
+                self.last_line = stmt_first_line(body);
+                self.stmt(body, indent + INDENT)
+            }
+        }
+    }
+
+    fn block(
+        &mut self,
+        header: String,
+        open: &Token,
+        stmts: &[Stmt],
+        close: &Token,
+        indent: usize,
+    ) -> usize {
+        let has_comments = self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|c| c.line < close.line);
+        if stmts.is_empty() && !has_comments {
+            self.line(indent, &format!("{}{{}}", header));
+        } else {
+            self.line(indent, &format!("{}{{", header));
+            self.last_line = open.line;
+            if stmts
+                .first()
+                .is_none_or(|first| stmt_first_line(first) != open.line)
+            {
+                self.trailing_comment(open.line);
+            }
+
+            self.stmts(stmts, indent + INDENT, close.line);
+            self.flush_comments(close.line, indent + INDENT);
+            self.line(indent, "}");
+        }
+
+        self.last_line = close.line;
+        close.line
+    }
+
+    // Removes the last written line and returns its text without indentation.
+    fn take_last_line(&mut self) -> String {
+        self.out.pop();
+        let start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        let line = self.out[start..].trim_start().to_string();
+        self.out.truncate(start);
+        line
+    }
+
+    fn expr(&self, e: &Expr, column: usize, indent: usize) -> String {
+        let text = flat(e);
+        if column + text.chars().count() <= self.width {
+            return text;
+        }
+
+        match e {
+            Expr::Binary(_, op, _) if op.ttype == Comma => {
+                let (first, rest) = binary_chain(e);
+                let cont = indent + INDENT;
+
+                let mut s = self.expr(first, column, indent);
+                for (_, operand) in rest {
+                    s.push_str(",\n");
+                    s.push_str(&" ".repeat(cont));
+                    s.push_str(&self.expr(operand, cont, cont));
+                }
+                s
+            }
+            Expr::Binary(..) => {
+                let (first, rest) = binary_chain(e);
+                let cont = indent + INDENT;
+
+                let mut s = self.expr(first, column, indent);
+                for (op, operand) in rest {
+                    s.push('\n');
+                    s.push_str(&" ".repeat(cont));
+                    s.push_str(op.lexeme);
+                    s.push(' ');
+                    s.push_str(&self.expr(operand, cont + op.lexeme.len() + 1, cont));
+                }
+                s
+            }
+            Expr::Grouping(inner) => format!("({})", self.expr(inner, column + 1, indent)),
+            Expr::Conditional(cond, then, otherwise) => {
+                let cont = indent + INDENT;
+                format!(
+                    "{}\n{}? {}\n{}: {}",
+                    self.expr(cond, column, indent),
+                    " ".repeat(cont),
+                    self.expr(then, cont + 2, cont),
+                    " ".repeat(cont),
+                    self.expr(otherwise, cont + 2, cont)
+                )
+            }
+            Expr::Assign(name, op, value) => {
+                let prefix = format!("{} {} ", name.lexeme, op.lexeme);
+                let value = self.expr(value, column + prefix.len(), indent);
+                format!("{}{}", prefix, value)
+            }
+            Expr::Unary(op, inner) => format!(
+                "{}{}",
+                op.lexeme,
+                self.expr(inner, column + op.lexeme.len(), indent)
+            ),
+            Expr::List(_, items) if !items.is_empty() => self.broken_items("[", items, "]", indent),
+            Expr::Map(_, entries) if !entries.is_empty() => self.broken_entries(entries, indent),
+            Expr::Call(callee, _, args) if !args.is_empty() => format!(
+                "{}{}",
+                self.expr(callee, column, indent),
+                self.broken_items("(", args, ")", indent)
+            ),
+            Expr::Get(obj, name) => format!("{}.{}", self.expr(obj, column, indent), name.lexeme),
+            Expr::Index(obj, _, index) => format!(
+                "{}[{}]",
+                self.expr(obj, column, indent),
+                self.expr(index, indent + INDENT, indent + INDENT)
+            ),
+            Expr::SetIndex(obj, index, op, value) => {
+                let prefix = format!("{}[{}] {} ", flat(obj), flat(index), op.lexeme);
+                let value = self.expr(value, column + prefix.chars().count(), indent);
+                format!("{}{}", prefix, value)
+            }
+            _ => text,
+        }
+    }
+
+    // Puts each element on its own line with a trailing comma.
+    fn broken_items(&self, open: &str, items: &[Expr], close: &str, indent: usize) -> String {
+        let cont = indent + INDENT;
+        let mut s = open.to_string();
+        for item in items {
+            s.push('\n');
+            s.push_str(&" ".repeat(cont));
+            s.push_str(&self.expr(item, cont, cont));
+            s.push(',');
+        }
+        s.push('\n');
+        s.push_str(&" ".repeat(indent));
+        s.push_str(close);
+        s
+    }
+
+    fn broken_entries(&self, entries: &[(Expr, Expr)], indent: usize) -> String {
+        let cont = indent + INDENT;
+        let mut s = "{".to_string();
+        for (k, v) in entries {
+            let key = self.expr(k, cont, cont);
+            let column = cont + key.chars().count() + 2;
+            s.push('\n');
+            s.push_str(&" ".repeat(cont));
+            s.push_str(&key);
+            s.push_str(": ");
+            s.push_str(&self.expr(v, column, cont));
+            s.push(',');
+        }
+        s.push('\n');
+        s.push_str(&" ".repeat(indent));
+        s.push('}');
+        s
+    }
+
+    fn flush_comments(&mut self, before_line: usize, indent: usize) {
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if c.line >= before_line {
+                break;
+            }
+            self.blank_line(c.line);
+            self.line(indent, c.text);
+            self.last_line = c.line + c.text.matches('\n').count();
+            self.next_comment += 1;
+        }
+    }
+
+    fn trailing_comment(&mut self, line: usize) {
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if c.line != line {
+                break;
+            }
+            self.out.pop();
+            self.out.push(' ');
+            self.out.push_str(c.text);
+            self.out.push('\n');
+            self.last_line = c.line + c.text.matches('\n').count();
+            self.next_comment += 1;
+        }
+    }
+
+    // Keeps at most one empty line where the source had one or more.
+    fn blank_line(&mut self, line: usize) {
+        if !self.out.is_empty() && line > self.last_line + 1 {
+            self.out.push('\n');
+        }
+    }
+
+    fn line(&mut self, indent: usize, text: &str) {
+        self.out.push_str(&" ".repeat(indent));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+}
+
+fn flat(e: &Expr) -> String {
+    match e {
+        Expr::Identifier(t) | Expr::Literal(t) => t.lexeme.to_string(),
+        Expr::Grouping(inner) => format!("({})", flat(inner)),
+        Expr::Unary(op, inner) => format!("{}{}", op.lexeme, flat(inner)),
+        Expr::Binary(l, op, r) if op.ttype == Comma => format!("{}, {}", flat(l), flat(r)),
+        Expr::Binary(l, op, r) => format!("{} {} {}", flat(l), op.lexeme, flat(r)),
+        Expr::Conditional(c, a, b) => format!("{} ? {} : {}", flat(c), flat(a), flat(b)),
+        Expr::Assign(name, op, value) => format!("{} {} {}", name.lexeme, op.lexeme, flat(value)),
+        Expr::List(_, items) => format!("[{}]", flat_items(items)),
+        Expr::Map(_, entries) => format!(
+            "{{{}}}",
+            entries
+                .iter()
+                .map(|(k, v)| format!("{}: {}", flat(k), flat(v)))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Expr::Index(obj, _, index) => format!("{}[{}]", flat(obj), flat(index)),
+        Expr::SetIndex(obj, index, op, value) => format!(
+            "{}[{}] {} {}",
+            flat(obj),
+            flat(index),
+            op.lexeme,
+            flat(value)
+        ),
+        Expr::Get(obj, name) => format!("{}.{}", flat(obj), name.lexeme),
+        Expr::Call(callee, _, args) => format!("{}({})", flat(callee), flat_items(args)),
+        Expr::Interpolation(parts) => astprinter::interpolation(parts, flat),
+    }
+}
+
+fn flat_items(items: &[Expr]) -> String {
+    items.iter().map(flat).collect::<Vec<String>>().join(", ")
+}
+
+// Splits a left-associative run of operators with the same precedence,
+// e.g. `a + b - c`, into its first operand and the `(op, operand)` pairs after it.
+fn binary_chain<'e, 'a>(e: &'e Expr<'a>) -> (&'e Expr<'a>, Vec<(&'e Token<'a>, &'e Expr<'a>)>) {
+    let mut rest: Vec<(&Token, &Expr)> = Vec::new();
+    let mut cur = e;
+
+    while let Expr::Binary(l, op, r) = cur {
+        if let Some((first_op, _)) = rest.first() {
+            if precedence(&first_op.ttype) != precedence(&op.ttype) {
+                break;
+            }
+        }
+        rest.push((op, r));
+        cur = l;
+    }
+
+    rest.reverse();
+    (cur, rest)
+}
+
+fn precedence(ttype: &TokenType) -> u8 {
+    match ttype {
+        EqualEqual | BangEqual => 1,
+        Greater | GreaterEqual | Less | LessEqual => 2,
+        Pipe => 3,
+        Caret => 4,
+        Ampersand => 5,
+        LessLess | GreaterGreater => 6,
+        Minus | Plus => 7,
+        Slash | Star | Percent => 8,
+        StarStar => 9,
+        _ => 0,
+    }
+}
+
+fn for_initializer(init: &Stmt) -> String {
+    match init {
+        Stmt::Var(name, None) => format!("var {}", name.lexeme),
+        Stmt::Var(name, Some(e)) => format!("var {} = {}", name.lexeme, flat(e)),
+        Stmt::Expression(e) => flat(e),
+        _ => unreachable!("loop initializers are declarations or expressions"),
+    }
+}
+
+fn stmt_first_line(stmt: &Stmt) -> usize {
+    match stmt {
+        Stmt::Expression(e) => first_line(e),
+        Stmt::Var(t, _)
+        | Stmt::Block(t, _, _)
+        | Stmt::If(t, ..)
+        | Stmt::While(t, ..)
+        | Stmt::For(t, ..)
+        | Stmt::ForIn(t, ..)
+        | Stmt::Throw(t, _)
+        | Stmt::Try(t, ..)
+        | Stmt::Import(t, ..)
+        | Stmt::Export(t, _)
+        | Stmt::Break(t)
+        | Stmt::Continue(t) => t.line,
+    }
+}
+
+// Follows the last nested statement in a loop, `else if` chains can be long.
+fn stmt_last_line(mut stmt: &Stmt) -> usize {
+    loop {
+        stmt = match stmt {
+            Stmt::Expression(e) => return last_line(e),
+            Stmt::Var(name, init) => return init.as_ref().map_or(name.line, |e| last_line(e)),
+            Stmt::Block(_, _, close) => return close.line,
+            Stmt::If(_, _, then, otherwise) => otherwise.as_deref().unwrap_or(then),
+            Stmt::While(_, _, body) | Stmt::For(.., body) | Stmt::ForIn(.., body) => body,
+            Stmt::Try(_, body, catch, finally) => match (catch, finally) {
+                (_, Some(finally)) => finally,
+                (Some((_, handler)), None) => handler,
+                (None, None) => body,
+            },
+            Stmt::Throw(_, e) => return last_line(e),
+            Stmt::Import(_, path, alias, _) => return alias.as_ref().unwrap_or(path).line,
+            Stmt::Export(_, var) => var,
+            Stmt::Break(t) | Stmt::Continue(t) => return t.line,
+        };
+    }
+}
+
+fn first_line(e: &Expr) -> usize {
+    match e {
+        Expr::Identifier(t) | Expr::Literal(t) | Expr::Unary(t, _) | Expr::Assign(t, _, _) => {
+            t.line
+        }
+        Expr::List(t, items) => items.first().map_or(t.line, first_line),
+        Expr::Map(t, entries) => entries.first().map_or(t.line, |(k, _)| first_line(k)),
+        Expr::Index(obj, _, _)
+        | Expr::SetIndex(obj, _, _, _)
+        | Expr::Get(obj, _)
+        | Expr::Call(obj, _, _) => first_line(obj),
+        Expr::Grouping(inner) => first_line(inner),
+        Expr::Binary(l, _, _) | Expr::Conditional(l, _, _) => first_line(l),
+        Expr::Interpolation(parts) => first_line(&parts[0]),
+    }
+}
+
+fn last_line(e: &Expr) -> usize {
+    match e {
+        Expr::Identifier(t) | Expr::Literal(t) => t.line,
+        Expr::Grouping(inner)
+        | Expr::Unary(_, inner)
+        | Expr::Binary(_, _, inner)
+        | Expr::Conditional(_, _, inner)
+        | Expr::Assign(_, _, inner)
+        | Expr::SetIndex(_, _, _, inner) => last_line(inner),
+        Expr::List(t, _)
+        | Expr::Map(t, _)
+        | Expr::Index(_, t, _)
+        | Expr::Get(_, t)
+        | Expr::Call(_, t, _) => t.line,
+        Expr::Interpolation(parts) => last_line(&parts[parts.len() - 1]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        format_source(source, DEFAULT_WIDTH).unwrap()
+    }
+
+    #[test]
+    fn spacing() {
+        assert_eq!(fmt("1+(3*2+-10)"), "1 + (3 * 2 + -10);\n");
+        assert_eq!(fmt("  !true   ==false 2;"), "!true == false;\n2;\n");
+        assert_eq!(
+            fmt("var  a=1 var b a+=-2**~a"),
+            "var a = 1;\nvar b;\na += -2 ** ~a;\n"
+        );
+        assert_eq!(fmt(""), "");
+        assert_eq!(fmt("\"a\\n${1+\"${2}\"}\""), "\"a\\n${1 + \"${2}\"}\";\n");
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            fmt("// head\n1+2 // trailing\n\n\n\n3\n// tail"),
+            "// head\n1 + 2; // trailing\n\n3;\n// tail\n"
+        );
+        assert_eq!(fmt("1 2 // two"), "1;\n2; // two\n");
+        assert_eq!(fmt("1 +\n// inner\n2"), "// inner\n1 + 2;\n");
+        assert_eq!(
+            fmt("/* a\n   b */\n1/*x*/+2 /* y */ /* z */"),
+            "/* a\n   b */\n1 + 2; /*x*/ /* y */ /* z */\n"
+        );
+    }
+
+    #[test]
+    fn wrapping() {
+        let s = format_source("111 + 222 - 333 * 444", 16).unwrap();
+        assert_eq!(s, "111\n    + 222\n    - 333 * 444;\n");
+        assert_eq!(format_source(&s, 16).unwrap(), s);
+
+        let s = format_source("var total = 111 + 222", 16).unwrap();
+        assert_eq!(s, "var total = 111\n    + 222;\n");
+
+        let s = format_source("ready ? first : second", 16).unwrap();
+        assert_eq!(s, "ready\n    ? first\n    : second;\n");
+        assert_eq!(fmt("a,b ?c:d"), "a, b ? c : d;\n");
+        let s = format_source("first, second, third", 16).unwrap();
+        assert_eq!(s, "first,\n    second,\n    third;\n");
+
+        assert_eq!(
+            fmt("xs [0]=[ 1,2 ,[] ].slice( 1 )"),
+            "xs[0] = [1, 2, []].slice(1);\n"
+        );
+        let s = format_source("var xs = [first, second]; xs.push(first + second)", 20).unwrap();
+        assert_eq!(
+            s,
+            "var xs = [\n    first,\n    second,\n];\nxs.push(\n    first + second,\n);\n"
+        );
+        assert_eq!(format_source(&s, 20).unwrap(), s);
+    }
+
+    #[test]
+    fn blocks_and_maps() {
+        assert_eq!(
+            fmt("{var a=1 {a}}{} var m={\"a\":1,2:{}}"),
+            "{\n    var a = 1;\n    {\n        a;\n    }\n}\n{}\nvar m = {\"a\": 1, 2: {}};\n"
+        );
+        assert_eq!(fmt("{1:2}; ({})"), "{1: 2};\n({});\n");
+        assert_eq!(
+            fmt("{ // open\n  1 // one\n  // end\n}"),
+            "{ // open\n    1; // one\n    // end\n}\n"
+        );
+
+        let s = format_source("var m = {\"first\": 1, \"second\": [2]}", 20).unwrap();
+        assert_eq!(
+            s,
+            "var m = {\n    \"first\": 1,\n    \"second\": [2],\n};\n"
+        );
+        assert_eq!(format_source(&s, 20).unwrap(), s);
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            fmt("if(a)b else if (c) {d} else{}"),
+            "if (a)\n    b;\nelse if (c) {\n    d;\n} else {}\n"
+        );
+        assert_eq!(
+            fmt("for(var i=0;i<3;i+=1){if(i)continue\nbreak}"),
+            "for (var i = 0; i < 3; i += 1) {\n    if (i)\n        continue;\n    break;\n}\n"
+        );
+        assert_eq!(fmt("for(;;){}"), "for (;;) {}\n");
+        assert_eq!(
+            fmt("while (a) { // loop\n a -= 1 } // done\nfor (var x in xs) x"),
+            "while (a) { // loop\n    a -= 1;\n} // done\nfor (var x in xs)\n    x;\n"
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        assert_eq!(
+            fmt("try{throw\"x\"}catch(e){e.message}finally{}"),
+            "try {\n    throw \"x\";\n} catch (e) {\n    e.message;\n} finally {}\n"
+        );
+        assert_eq!(fmt("try {} finally { 1 }"), "try {} finally {\n    1;\n}\n");
+    }
+
+    #[test]
+    fn modules() {
+        assert_eq!(
+            fmt("import\"a.lox\"as a import{b,c as d}from\"b.lox\" import \"c.lox\" export var e=1"),
+            "import \"a.lox\" as a;\nimport { b, c as d } from \"b.lox\";\nimport \"c.lox\";\nexport var e = 1;\n"
+        );
+    }
+
+    #[test]
+    fn invalid_source() {
+        assert!(format_source("1 +", DEFAULT_WIDTH).is_err());
+    }
+}
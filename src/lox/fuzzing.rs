@@ -0,0 +1,153 @@
+// Entry points for the fuzz targets in fuzz/, one per stage. Whatever the
+// input, none of them may panic; failing with an error is fine.
+
+use super::interpreter::Interpreter;
+use super::parser::Parser;
+use super::scanner::Scanner;
+use super::stdlib::{Capabilities, Clock};
+use super::{formatter, DEFAULT_WIDTH};
+use std::io;
+use std::rc::Rc;
+
+// Loops are cut off after this many iterations, so every input finishes quickly.
+const STEP_LIMIT: usize = 1000;
+
+pub fn scan(source: &str) {
+    let _ = Scanner::new(source).scan_tokens();
+    let _ = Scanner::lossless(source).scan_tokens();
+}
+
+pub fn parse(source: &str) {
+    let mut s = Scanner::new(source);
+    if let Ok(tokens) = s.scan_tokens() {
+        let _ = Parser::new(tokens).parse();
+    }
+}
+
+pub fn format(source: &str) {
+    let _ = formatter::format_source(source, DEFAULT_WIDTH);
+}
+
+// Runs without access to files, the environment or the process, and with a
+// clock that doesn't move, so `sleep` returns at once. Output is thrown away.
+pub fn run(source: &str) {
+    let mut s = Scanner::new(source);
+    let tokens = match s.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(_) => return,
+    };
+    let mut p = Parser::new(tokens);
+    let stmts = match p.parse() {
+        Ok(stmts) => stmts,
+        Err(_) => return,
+    };
+
+    let mut interpreter = Interpreter::with_capabilities(Capabilities::none());
+    interpreter.set_clock(Rc::new(StoppedClock));
+    interpreter.set_step_limit(STEP_LIMIT);
+    interpreter.set_output(Box::new(io::sink()));
+    let _ = interpreter.exec(stmts);
+}
+
+struct StoppedClock;
+
+impl Clock for StoppedClock {
+    fn now(&self) -> f64 {
+        0.0
+    }
+
+    fn monotonic(&self) -> f64 {
+        0.0
+    }
+
+    fn sleep(&self, _seconds: f64) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::map::OrderedMap;
+    use super::super::value::Value;
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn all_stages(source: &str) {
+        scan(source);
+        parse(source);
+        format(source);
+        run(source);
+    }
+
+    // Inputs that made a fuzz target panic, kept so the bugs stay fixed.
+    #[test]
+    fn regressions() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions");
+        for entry in fs::read_dir(dir).unwrap() {
+            let bytes = fs::read(entry.unwrap().path()).unwrap();
+            all_stages(&String::from_utf8_lossy(&bytes));
+        }
+    }
+
+    #[test]
+    fn limits() {
+        // As deep as the parser allows, which every later stage must handle too
+        // with the stack `with_stack` gives them.
+        super::super::with_stack(|| {
+            for (open, close) in &[
+                ("(", ")"),
+                ("[", "]"),
+                ("{", "}"),
+                ("-", ""),
+                ("1 + ", ""),
+                ("1, ", ""),
+                ("1 ? ", " : 1"),
+                ("", "[0]"),
+                ("", ".len()"),
+            ] {
+                all_stages(&format!("{}1{}", open.repeat(98), close.repeat(98)));
+                all_stages(&format!(
+                    "{}1{}",
+                    open.repeat(100_000),
+                    close.repeat(100_000)
+                ));
+            }
+        });
+        all_stages(&format!(
+            "if (0) 0{} else 1",
+            " else if (0) 0".repeat(10_000)
+        ));
+        all_stages("while (1) {}");
+        all_stages("var xs = []; for (;;) xs = [xs]; xs");
+        all_stages("import \"time\" as time; time.sleep(1e12)");
+        all_stages("\"a\".repeat(1 << 40)");
+        all_stages("var s = \"ab\"; while (true) s += s;");
+        all_stages("var s = \"ab\"; while (true) s = \"${s}${s}\";");
+    }
+
+    // Values can be nested at runtime far deeper than the parser allows, and
+    // comparing, printing and dropping them must not overflow the stack.
+    #[test]
+    fn deep_values() {
+        let deep = |wrap: fn(Value) -> Value| {
+            let mut v = Value::Nil;
+            for _ in 0..200_000 {
+                v = wrap(v);
+            }
+            v
+        };
+        let list = |v| Value::list(vec![v]);
+        let map = |v| {
+            let mut m = OrderedMap::new();
+            let _ = m.insert(Value::Int(0), v);
+            Value::map(m)
+        };
+
+        for wrap in &[list as fn(Value) -> Value, map] {
+            let (a, b) = (deep(*wrap), deep(*wrap));
+            assert!(a == b);
+            let printed = a.to_string();
+            assert!(printed.contains("[...]") || printed.contains("{...}"));
+        }
+        assert!(deep(list) != deep(map));
+    }
+}
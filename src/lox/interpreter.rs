@@ -1,124 +1,1580 @@
 use super::ast::*;
 use super::errors;
-use super::parser::Stmt;
+use super::map::OrderedMap;
+use super::methods;
+use super::module::{self, Module};
+use super::parser::{Parser, Stmt};
+use super::scanner::Scanner;
+use super::stdlib::{self, Capabilities, Clock, ClockCell, SystemClock};
 use super::tokens::*;
 use super::value::Value;
 use errors::CompileErrorType::*;
 use errors::{CompileError, CompileErrorType};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-pub struct Interpreter {}
+// How a statement finished, so `break` and `continue` can unwind to the enclosing loop.
+#[derive(Debug, PartialEq)]
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+}
+
+// Notified before every statement once attached with `set_debugger`.
+pub trait Debugger {
+    // `depth` is the number of imports running around the statement, 0 for the
+    // script itself. Returning only once the user resumes pauses the script,
+    // returning an error stops it.
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        depth: usize,
+        line: usize,
+    ) -> Result<(), CompileError>;
+}
+
+pub struct Interpreter {
+    // Variables of each enclosing block, innermost last. The first scope holds the globals.
+    scopes: Vec<HashMap<String, Value>>,
+    // Scopes of the files whose imports are running, outermost first.
+    suspended: Vec<Vec<HashMap<String, Value>>>,
+    // Directory of the file being run, imports are looked up relative to it first.
+    dir: PathBuf,
+    search_path: Vec<PathBuf>,
+    // Every module imported so far, so each file is only evaluated once.
+    modules: HashMap<PathBuf, Rc<Module>>,
+    // Files whose evaluation is in progress, the innermost last, to report import cycles.
+    loading: Vec<PathBuf>,
+    capabilities: Capabilities,
+    clock: ClockCell,
+    // Loop iterations run so far, and how many are allowed.
+    steps: usize,
+    step_limit: Option<usize>,
+    // Where expression statements print their values.
+    output: Box<dyn Write>,
+    debugger: Option<Box<dyn Debugger>>,
+}
 
 #[allow(dead_code)]
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {}
+        Self::with_capabilities(Capabilities::all())
     }
 
-    pub fn exec(self: &mut Self, stmts: &[Stmt]) -> Result<(), CompileError> {
-        for stmt in stmts {
-            self.exec_stmt(stmt)?;
+    // An interpreter whose scripts may only reach outside it as far as `capabilities` allows.
+    pub fn with_capabilities(capabilities: Capabilities) -> Self {
+        let clock: ClockCell = Rc::new(RefCell::new(Rc::new(SystemClock::new())));
+        Interpreter {
+            scopes: vec![stdlib::globals(capabilities, &clock)],
+            suspended: Vec::new(),
+            dir: PathBuf::from("."),
+            search_path: Vec::new(),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            capabilities,
+            clock,
+            steps: 0,
+            step_limit: None,
+            output: Box::new(io::stdout()),
+            debugger: None,
         }
+    }
+
+    // Where `clock()` and the time module get the time from, also when they
+    // were already imported or handed around.
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        *self.clock.borrow_mut() = clock;
+    }
+
+    // Directories searched for imports that aren't found next to the importing file.
+    pub fn set_search_path(&mut self, dirs: Vec<PathBuf>) {
+        self.search_path = dirs;
+    }
+
+    // Runs the following statements as the contents of `path`.
+    pub fn set_file(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.dir = path
+            .parent()
+            .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+        self.loading = vec![path];
+    }
+
+    // Fails scripts with a runtime error once their loops have run `limit`
+    // iterations in total, so untrusted code can't run forever.
+    pub fn set_step_limit(&mut self, limit: usize) {
+        self.step_limit = Some(limit);
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
 
+    pub fn set_debugger(&mut self, debugger: Box<dyn Debugger>) {
+        self.debugger = Some(debugger);
+    }
+
+    // The scopes of the file running at `depth`, see `Debugger`, globals first.
+    pub fn scopes_at(&self, depth: usize) -> &[HashMap<String, Value>] {
+        self.suspended.get(depth).unwrap_or(&self.scopes)
+    }
+
+    // The file running at `depth`, if the script was run from one.
+    pub fn file_at(&self, depth: usize) -> Option<&Path> {
+        self.loading.get(depth).map(PathBuf::as_path)
+    }
+
+    // Evaluates the expression in `source` with the variables of the file at `depth`.
+    pub fn evaluate_at(&mut self, depth: usize, source: &str) -> Result<Value, CompileError> {
+        let mut s = Scanner::new(source);
+        let tokens = s.scan_tokens()?;
+        let mut p = Parser::new(tokens);
+        let e = match p.parse()?.as_slice() {
+            [Stmt::Expression(e)] => e,
+            _ => return self.error(1, InvalidSyntax, "Expect a single expression."),
+        };
+
+        if depth >= self.suspended.len() {
+            return self.evaluate(e);
+        }
+        mem::swap(&mut self.scopes, &mut self.suspended[depth]);
+        let result = self.evaluate(e);
+        mem::swap(&mut self.scopes, &mut self.suspended[depth]);
+        result
+    }
+
+    pub fn exec(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        self.exec_stmts(stmts)?;
         Ok(())
     }
 
-    fn exec_stmt(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+    // Stops at the first statement that breaks out of the normal flow and returns how.
+    fn exec_stmts(&mut self, stmts: &[Stmt]) -> Result<Flow, CompileError> {
+        for stmt in stmts {
+            let flow = self.exec_stmt(stmt)?;
+            if flow != Flow::Normal {
+                return Ok(flow);
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt) -> Result<Flow, CompileError> {
+        // Blocks and exports only hold statements, the debugger stops at those.
+        if self.debugger.is_some() && !matches!(stmt, Stmt::Block(..) | Stmt::Export(..)) {
+            self.debug(stmt)?;
+        }
+
         match stmt {
             Stmt::Expression(expr) => {
                 let r = self.evaluate(expr)?;
-                println!("{}", r);
-            } //_ => {}
+                let _ = writeln!(self.output, "{}", r);
+            }
+            Stmt::Var(name, init) => {
+                let v = match init {
+                    Some(e) => self.evaluate(e)?,
+                    None => Value::Nil,
+                };
+                self.define(name.lexeme, v);
+            }
+            Stmt::Block(_, stmts, _) => return self.scoped(|this| this.exec_stmts(stmts)),
+            // `else if` chains are followed in a loop, they can be longer than the
+            // nesting limit.
+            Stmt::If(_, cond, then, otherwise) => {
+                let (mut cond, mut then, mut otherwise) = (cond, then, otherwise);
+                loop {
+                    if self.evaluate(cond)?.is_truthy() {
+                        return self.exec_stmt(then);
+                    }
+                    match otherwise.as_deref() {
+                        Some(branch @ Stmt::If(_, c, t, o)) => {
+                            if self.debugger.is_some() {
+                                self.debug(branch)?;
+                            }
+                            cond = c;
+                            then = t;
+                            otherwise = o;
+                        }
+                        Some(otherwise) => return self.exec_stmt(otherwise),
+                        None => break,
+                    }
+                }
+            }
+            Stmt::While(keyword, cond, body) => {
+                while self.evaluate(cond)?.is_truthy() {
+                    self.step(keyword)?;
+                    if self.exec_stmt(body)? == Flow::Break {
+                        break;
+                    }
+                }
+            }
+            Stmt::For(keyword, init, cond, incr, body) => {
+                return self.scoped(|this| {
+                    if let Some(init) = init {
+                        this.exec_stmt(init)?;
+                    }
+                    loop {
+                        if let Some(cond) = cond {
+                            if !this.evaluate(cond)?.is_truthy() {
+                                break;
+                            }
+                        }
+                        this.step(keyword)?;
+                        if this.exec_stmt(body)? == Flow::Break {
+                            break;
+                        }
+                        if let Some(incr) = incr {
+                            this.evaluate(incr)?;
+                        }
+                    }
+                    Ok(Flow::Normal)
+                });
+            }
+            Stmt::ForIn(keyword, name, iterable, body) => {
+                let iterable = self.evaluate(iterable)?;
+                self.for_in(keyword, name, iterable, body)?;
+            }
+            Stmt::Throw(keyword, value) => {
+                let v = self.evaluate(value)?;
+                let v = match &v {
+                    // Rethrowing a caught error keeps where it came from.
+                    Value::Error(e) => {
+                        let mut trace = e.trace.clone();
+                        trace.push(keyword.line);
+                        Value::error(e.message.clone(), e.line, trace)
+                    }
+                    _ => v,
+                };
+                let text = v.repr();
+                return self.error(keyword.line, Thrown(v), &text);
+            }
+            Stmt::Try(_, body, catch, finally) => {
+                let mut result = self.exec_stmt(body);
+                if let (Err(e), Some((name, handler))) = (&result, catch) {
+                    if let Some(v) = caught(e) {
+                        result = self.scoped(|this| {
+                            this.define(name.lexeme, v);
+                            this.exec_stmt(handler)
+                        });
+                    }
+                }
+
+                // An error or jump out of `finally` replaces the outcome of the other blocks.
+                if let Some(finally) = finally {
+                    let flow = self.exec_stmt(finally)?;
+                    if flow != Flow::Normal {
+                        return Ok(flow);
+                    }
+                }
+                return result;
+            }
+            Stmt::Import(keyword, path, alias, names) => {
+                let module = self.import(keyword, path)?;
+                if let Some(alias) = alias {
+                    self.define(alias.lexeme, Value::Module(module.clone()));
+                }
+                for (name, local) in names {
+                    match module.exports.get(name.lexeme) {
+                        Some(v) => self.define(local.lexeme, v.clone()),
+                        None => {
+                            return self.error(
+                                name.line,
+                                EvaluationFailed,
+                                &format!("Module {} has no export '{}'", path.lexeme, name.lexeme),
+                            )
+                        }
+                    }
+                }
+            }
+            Stmt::Export(_, var) => return self.exec_stmt(var),
+            Stmt::Break(_) => return Ok(Flow::Break),
+            Stmt::Continue(_) => return Ok(Flow::Continue),
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    // Lists yield their elements, maps their keys and strings their characters. The loop
+    // walks a snapshot, so changes the body makes to the collection don't affect it.
+    fn for_in(
+        &mut self,
+        keyword: &Token,
+        name: &Token,
+        iterable: Value,
+        body: &Stmt,
+    ) -> Result<(), CompileError> {
+        let items: Vec<Value> = match &iterable {
+            Value::List(items) => items.borrow().clone(),
+            Value::Map(entries) => entries.borrow().keys().cloned().collect(),
+            Value::Str(s) => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+            v => {
+                return self.error(
+                    keyword.line,
+                    EvaluationFailed,
+                    &format!("Can't iterate over {}", v.type_name()),
+                )
+            }
+        };
+
+        for item in items {
+            self.step(keyword)?;
+            let flow = self.scoped(|this| {
+                this.define(name.lexeme, item);
+                this.exec_stmt(body)
+            })?;
+            if flow == Flow::Break {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn import(&mut self, keyword: &Token, path: &Token) -> Result<Rc<Module>, CompileError> {
+        let name = match path.literal {
+            Some(Value::Str(ref name)) => name,
+            _ => return self.error(path.line, EvaluationFailed, "Invalid module path"),
+        };
+        // Built-in modules are cached under their name.
+        let builtin = PathBuf::from(name);
+        if let Some(module) = self.modules.get(&builtin) {
+            return Ok(module.clone());
+        }
+        if let Some(exports) = stdlib::module(name, &self.clock) {
+            let module = Rc::new(Module {
+                path: builtin.clone(),
+                exports,
+            });
+            self.modules.insert(builtin, module.clone());
+            return Ok(module);
+        }
+
+        // Sandboxed scripts can only import built-in modules.
+        if !self.capabilities.files {
+            let msg = format!(
+                "Importing \"{}\" needs file access, which is disabled",
+                name
+            );
+            return self.error(keyword.line, EvaluationFailed, &msg);
         }
 
+        let file = match module::resolve(name, &self.dir, &self.search_path) {
+            Ok(file) => file,
+            Err(msg) => return self.error(keyword.line, EvaluationFailed, &msg),
+        };
+
+        if let Some(module) = self.modules.get(&file) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self.loading.iter().position(|f| *f == file) {
+            let cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .chain(Some(&file))
+                .map(|f| f.display().to_string())
+                .collect();
+            let msg = format!("Import cycle: {}", cycle.join(" -> "));
+            return self.error(keyword.line, EvaluationFailed, &msg);
+        }
+
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(e) => {
+                let msg = format!("{}: {}", file.display(), e);
+                return self.error(keyword.line, EvaluationFailed, &msg);
+            }
+        };
+
+        // The module gets its own globals, then everything is put back for the importer.
+        let scopes = mem::replace(
+            &mut self.scopes,
+            vec![stdlib::globals(self.capabilities, &self.clock)],
+        );
+        self.suspended.push(scopes);
+        let dir = mem::replace(&mut self.dir, file.parent().unwrap_or(&file).to_path_buf());
+        self.loading.push(file.clone());
+        let exports = self.run_module(&source);
+        self.loading.pop();
+        self.dir = dir;
+        self.scopes = self.suspended.pop().unwrap_or_default();
+
+        let exports = match exports {
+            Ok(exports) => exports,
+            Err(e) => match e.err {
+                Thrown(_) | Interrupted => return Err(e),
+                _ => {
+                    let msg = format!("In {}, line {}: {}", file.display(), e.line, e.message());
+                    return self.error(keyword.line, EvaluationFailed, &msg);
+                }
+            },
+        };
+
+        let module = Rc::new(Module {
+            path: file.clone(),
+            exports,
+        });
+        self.modules.insert(file, module.clone());
+        Ok(module)
+    }
+
+    fn run_module(&mut self, source: &str) -> Result<HashMap<String, Value>, CompileError> {
+        let mut s = Scanner::new(source);
+        let tokens = s.scan_tokens()?;
+        let mut p = Parser::new(tokens);
+        let stmts = p.parse()?;
+        self.exec(stmts)?;
+
+        let mut exports = HashMap::new();
+        for stmt in stmts {
+            if let Stmt::Export(_, var) = stmt {
+                if let Stmt::Var(name, _) = &**var {
+                    let v = self.lookup(name.lexeme).cloned().unwrap_or(Value::Nil);
+                    exports.insert(name.lexeme.to_string(), v);
+                }
+            }
+        }
+        Ok(exports)
+    }
+
+    // Lets the debugger look at the statement about to run, and pause there.
+    fn debug(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        if let Some(mut debugger) = self.debugger.take() {
+            let depth = self.suspended.len();
+            let result = debugger.before_statement(self, depth, stmt.line());
+            self.debugger = Some(debugger);
+            result?;
+        }
         Ok(())
     }
 
-    fn evaluate(&mut self, node: &Box<Expr>) -> Result<Value, CompileError> {
+    // Counts one loop iteration against the step limit.
+    fn step(&mut self, keyword: &Token) -> Result<(), CompileError> {
+        self.steps += 1;
+        match self.step_limit {
+            Some(limit) if self.steps > limit => self.error(
+                keyword.line,
+                EvaluationFailed,
+                &format!("Step limit of {} loop iterations exceeded", limit),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    // Runs `f` with a new innermost scope, which is dropped again even if `f` fails.
+    fn scoped<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, CompileError>,
+    ) -> Result<T, CompileError> {
+        self.scopes.push(HashMap::new());
+        let result = f(self);
+        self.scopes.pop();
+        result
+    }
+
+    pub fn evaluate(&mut self, node: &Expr) -> Result<Value, CompileError> {
         use Value::*;
-        return match **node {
+        match *node {
             Expr::Binary(ref left, ref op, ref right) => {
                 let l = self.evaluate(left)?;
                 let r = self.evaluate(right)?;
-
-                match op.ttype {
-                    TokenType::Plus => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Number(l + r)),
-                        (Str(l), Str(r)) => Ok(Str(l + &r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for +"),
-                    },
-                    TokenType::Minus => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Number(l - r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for -"),
-                    },
-                    TokenType::Star => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Number(l * r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for *"),
-                    },
-                    TokenType::Slash => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Number(l / r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for /"),
-                    },
-                    TokenType::Greater => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Bool(l > r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for >"),
-                    },
-                    TokenType::GreaterEqual => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Bool(l >= r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for >="),
-                    },
-                    TokenType::Less => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Bool(l < r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for <"),
-                    },
-                    TokenType::LessEqual => match (l, r) {
-                        (Number(l), Number(r)) => Ok(Bool(l <= r)),
-                        _ => self.error(op.line, EvaluationFailed, "Unsupported types for <="),
-                    },
-                    TokenType::EqualEqual => Ok(Bool(l == r)),
-                    TokenType::BangEqual => Ok(Bool(l != r)),
-                    _ => self.error(0, EvaluationFailed, "Code path is not covered yet"),
-                }
+                self.binary(op, &op.ttype, l, r)
             }
             Expr::Unary(ref op, ref t) => {
                 let v = self.evaluate(t)?;
                 match op.ttype {
                     TokenType::Minus => match v {
                         Number(n) => Ok(Number(-n)),
+                        Int(i) => match i.checked_neg() {
+                            Some(i) => Ok(Int(i)),
+                            None => {
+                                self.error(op.line, EvaluationFailed, "Integer overflow in unary -")
+                            }
+                        },
                         _ => self.error(op.line, EvaluationFailed, "Unsupported types for unary -"),
                     },
+                    TokenType::Tilde => match v {
+                        Int(i) => Ok(Int(!i)),
+                        _ => {
+                            self.error(op.line, EvaluationFailed, "Operand of ~ must be an integer")
+                        }
+                    },
                     TokenType::Bang => Ok(Bool(!v.is_truthy())),
                     _ => self.error(0, EvaluationFailed, "Wrong unary operator"),
                 }
             }
+            Expr::Identifier(ref name) => match self.lookup(name.lexeme) {
+                Some(v) => Ok(v.clone()),
+                None => self.error(
+                    name.line,
+                    EvaluationFailed,
+                    &format!("Undefined variable '{}'", name.lexeme),
+                ),
+            },
+            Expr::Assign(ref name, ref op, ref value) => {
+                let v = self.evaluate(value)?;
+                let current = match self.lookup(name.lexeme) {
+                    Some(current) => current.clone(),
+                    None => {
+                        return self.error(
+                            name.line,
+                            EvaluationFailed,
+                            &format!("Undefined variable '{}'", name.lexeme),
+                        )
+                    }
+                };
+
+                let v = self.compound(op, current, v)?;
+                if let Some(slot) = self.lookup_mut(name.lexeme) {
+                    *slot = v.clone();
+                }
+                Ok(v)
+            }
+            Expr::List(_, ref items) => Ok(Value::list(self.evaluate_all(items)?)),
+            Expr::Map(ref brace, ref entries) => {
+                let mut map = OrderedMap::new();
+                for (k, v) in entries {
+                    let k = self.evaluate(k)?;
+                    let v = self.evaluate(v)?;
+                    if let Err(msg) = map.insert(k, v) {
+                        return self.error(brace.line, EvaluationFailed, &msg);
+                    }
+                }
+                Ok(Value::map(map))
+            }
+            Expr::Index(ref obj, ref bracket, ref index) => {
+                let obj = self.evaluate(obj)?;
+                let index = self.evaluate(index)?;
+                self.get_index(bracket, &obj, &index)
+            }
+            Expr::SetIndex(ref obj, ref index, ref op, ref value) => {
+                let obj = self.evaluate(obj)?;
+                let index = self.evaluate(index)?;
+                let v = self.evaluate(value)?;
 
-            Expr::Literal(ref t) => Ok(t.literal.as_ref().unwrap().clone()),
+                // Plain `=` may add a new key to a map, so there's no current value to read.
+                let v = if op.ttype == TokenType::Equal {
+                    v
+                } else {
+                    let current = self.get_index(op, &obj, &index)?;
+                    self.compound(op, current, v)?
+                };
+                self.set_index(op, &obj, &index, v.clone())?;
+                Ok(v)
+            }
+            Expr::Call(ref callee, ref paren, ref args) => {
+                let f = match **callee {
+                    // Members of modules are values, other `.name(...)` calls are
+                    // built-in methods that are looked up by name.
+                    Expr::Get(ref obj, ref name) => match self.evaluate(obj)? {
+                        obj @ Value::Module(_) => self.get_field(name, &obj)?,
+                        obj => {
+                            let args = self.evaluate_all(args)?;
+                            return match methods::call(&obj, name.lexeme, args) {
+                                Ok(v) => Ok(v),
+                                Err(msg) => self.error(name.line, EvaluationFailed, &msg),
+                            };
+                        }
+                    },
+                    _ => self.evaluate(callee)?,
+                };
+
+                let args = self.evaluate_all(args)?;
+                match &f {
+                    Value::Native(f) => match (f.function)(args) {
+                        Ok(v) => Ok(v),
+                        Err(msg) => self.error(paren.line, EvaluationFailed, &msg),
+                    },
+                    _ => self.error(
+                        paren.line,
+                        EvaluationFailed,
+                        &format!("Can't call {}", f.type_name()),
+                    ),
+                }
+            }
+            Expr::Get(ref obj, ref name) => {
+                let obj = self.evaluate(obj)?;
+                self.get_field(name, &obj)
+            }
+
+            Expr::Conditional(ref cond, ref then, ref otherwise) => {
+                if self.evaluate(cond)?.is_truthy() {
+                    self.evaluate(then)
+                } else {
+                    self.evaluate(otherwise)
+                }
+            }
+
+            // `true`, `false` and `nil` are keywords without a literal value.
+            Expr::Literal(ref t) => Ok(match (&t.literal, &t.ttype) {
+                (Some(v), _) => v.clone(),
+                (None, TokenType::True) => Value::Bool(true),
+                (None, TokenType::False) => Value::Bool(false),
+                (None, _) => Value::Nil,
+            }),
             Expr::Grouping(ref g) => self.evaluate(g),
+            Expr::Interpolation(ref parts) => {
+                let mut s = String::new();
+                for part in parts {
+                    s += &self.evaluate(part)?.to_string();
+                    if let Err(msg) = methods::check_length("String", s.len()) {
+                        return self.error(part.line(), EvaluationFailed, &msg);
+                    }
+                }
+                Ok(Str(s))
+            }
+        }
+    }
+
+    fn get_field(&self, name: &Token, obj: &Value) -> Result<Value, CompileError> {
+        match methods::field(obj, name.lexeme) {
+            Ok(v) => Ok(v),
+            Err(msg) => self.error(name.line, EvaluationFailed, &msg),
+        }
+    }
+
+    fn define(&mut self, name: &str, v: Value) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), v);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    fn evaluate_all(&mut self, exprs: &[Expr]) -> Result<Vec<Value>, CompileError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for e in exprs {
+            values.push(self.evaluate(e)?);
+        }
+        Ok(values)
+    }
+
+    // New value for an assignment with `op`, combining it with the current one for `+=` and friends.
+    fn compound(&self, op: &Token, current: Value, v: Value) -> Result<Value, CompileError> {
+        match op.ttype {
+            TokenType::PlusEqual => self.binary(op, &TokenType::Plus, current, v),
+            TokenType::MinusEqual => self.binary(op, &TokenType::Minus, current, v),
+            TokenType::StarEqual => self.binary(op, &TokenType::Star, current, v),
+            TokenType::SlashEqual => self.binary(op, &TokenType::Slash, current, v),
+            _ => Ok(v),
+        }
+    }
+
+    fn get_index(&self, at: &Token, obj: &Value, index: &Value) -> Result<Value, CompileError> {
+        match obj {
+            Value::List(items) => {
+                let items = items.borrow();
+                match methods::index("List", index, items.len()) {
+                    Ok(i) => Ok(items[i].clone()),
+                    Err(msg) => self.error(at.line, EvaluationFailed, &msg),
+                }
+            }
+            // The character at a position, as a string.
+            Value::Str(s) => match methods::index("String", index, s.chars().count()) {
+                Ok(i) => Ok(Value::Str(s.chars().nth(i).unwrap_or_default().to_string())),
+                Err(msg) => self.error(at.line, EvaluationFailed, &msg),
+            },
+            Value::Map(entries) => match entries.borrow().get(index) {
+                Ok(Some(v)) => Ok(v.clone()),
+                Ok(None) => self.error(
+                    at.line,
+                    EvaluationFailed,
+                    &format!("Key {} not found", index.repr()),
+                ),
+                Err(msg) => self.error(at.line, EvaluationFailed, &msg),
+            },
+            _ => self.error(
+                at.line,
+                EvaluationFailed,
+                &format!("Can't index into {}", obj.type_name()),
+            ),
+        }
+    }
+
+    fn set_index(
+        &self,
+        at: &Token,
+        obj: &Value,
+        index: &Value,
+        v: Value,
+    ) -> Result<(), CompileError> {
+        match obj {
+            Value::List(items) => {
+                let mut items = items.borrow_mut();
+                match methods::index("List", index, items.len()) {
+                    Ok(i) => {
+                        items[i] = v;
+                        Ok(())
+                    }
+                    Err(msg) => self.error(at.line, EvaluationFailed, &msg),
+                }
+            }
+            Value::Map(entries) => match entries.borrow_mut().insert(index.clone(), v) {
+                Ok(()) => Ok(()),
+                Err(msg) => self.error(at.line, EvaluationFailed, &msg),
+            },
+            _ => self.error(
+                at.line,
+                EvaluationFailed,
+                &format!("Can't index into {}", obj.type_name()),
+            ),
+        }
+    }
+
+    // `ttype` is the operation to apply, which differs from `op` for compound assignments.
+    fn binary(
+        &self,
+        op: &Token,
+        ttype: &TokenType,
+        l: Value,
+        r: Value,
+    ) -> Result<Value, CompileError> {
+        use Value::*;
+        match ttype {
+            TokenType::Plus => match (l, r) {
+                (Str(ref l), Str(ref r)) => {
+                    if let Err(msg) = methods::check_length("String", l.len() + r.len()) {
+                        return self.error(op.line, EvaluationFailed, &msg);
+                    }
+                    Ok(Str(format!("{}{}", l, r)))
+                }
+                (l, r) => self.arithmetic(op, l, r, i64::checked_add, |l, r| l + r),
+            },
+            TokenType::Minus => self.arithmetic(op, l, r, i64::checked_sub, |l, r| l - r),
+            TokenType::Star => self.arithmetic(op, l, r, i64::checked_mul, |l, r| l * r),
+            TokenType::Slash => match (l, r) {
+                (Int(_), Int(0)) => self.error(op.line, EvaluationFailed, "Division by zero"),
+                (l, r) => self.arithmetic(op, l, r, i64::checked_div, |l, r| l / r),
+            },
+            TokenType::Percent => match (l, r) {
+                (Int(_), Int(0)) => self.error(op.line, EvaluationFailed, "Division by zero"),
+                (l, r) => self.arithmetic(op, l, r, i64::checked_rem, |l, r| l % r),
+            },
+            TokenType::StarStar => match (l, r) {
+                (Int(l), Int(r)) if r >= 0 => {
+                    match u32::try_from(r).ok().and_then(|r| l.checked_pow(r)) {
+                        Some(i) => Ok(Int(i)),
+                        None => self.error(op.line, EvaluationFailed, "Integer overflow in **"),
+                    }
+                }
+                // A negative exponent gives a fraction.
+                (Int(l), Int(r)) => Ok(Number((l as f64).powf(r as f64))),
+                (l, r) => self.arithmetic(op, l, r, |_, _| None, f64::powf),
+            },
+            TokenType::Ampersand => self.bitwise(op, l, r, |l, r| Some(l & r)),
+            TokenType::Pipe => self.bitwise(op, l, r, |l, r| Some(l | r)),
+            TokenType::Caret => self.bitwise(op, l, r, |l, r| Some(l ^ r)),
+            TokenType::LessLess => self.bitwise(op, l, r, |l, r| {
+                u32::try_from(r).ok().and_then(|r| l.checked_shl(r))
+            }),
+            TokenType::GreaterGreater => self.bitwise(op, l, r, |l, r| {
+                u32::try_from(r).ok().and_then(|r| l.checked_shr(r))
+            }),
+            TokenType::Greater => self.comparison(op, l, r, i64::gt, f64::gt),
+            TokenType::GreaterEqual => self.comparison(op, l, r, i64::ge, f64::ge),
+            TokenType::Less => self.comparison(op, l, r, i64::lt, f64::lt),
+            TokenType::LessEqual => self.comparison(op, l, r, i64::le, f64::le),
+            TokenType::EqualEqual => Ok(Bool(l == r)),
+            TokenType::BangEqual => Ok(Bool(l != r)),
+            TokenType::Comma => Ok(r),
             _ => self.error(0, EvaluationFailed, "Code path is not covered yet"),
-        };
+        }
+    }
+
+    // Two Ints stay integral and fail on overflow instead of wrapping around,
+    // any other pair of numbers is computed as floats.
+    fn arithmetic(
+        &self,
+        op: &Token,
+        l: Value,
+        r: Value,
+        int_op: fn(i64, i64) -> Option<i64>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Result<Value, CompileError> {
+        match (l, r) {
+            (Value::Int(l), Value::Int(r)) => match int_op(l, r) {
+                Some(i) => Ok(Value::Int(i)),
+                None => self.error(
+                    op.line,
+                    EvaluationFailed,
+                    &format!("Integer overflow in {}", op.lexeme),
+                ),
+            },
+            (l, r) => match (l.as_f64(), r.as_f64()) {
+                (Some(l), Some(r)) => Ok(Value::Number(float_op(l, r))),
+                _ => self.error(
+                    op.line,
+                    EvaluationFailed,
+                    &format!("Unsupported types for {}", op.lexeme),
+                ),
+            },
+        }
+    }
+
+    // Shifts fail when the amount is negative or not below 64.
+    fn bitwise(
+        &self,
+        op: &Token,
+        l: Value,
+        r: Value,
+        int_op: fn(i64, i64) -> Option<i64>,
+    ) -> Result<Value, CompileError> {
+        match (l, r) {
+            (Value::Int(l), Value::Int(r)) => match int_op(l, r) {
+                Some(i) => Ok(Value::Int(i)),
+                None => self.error(
+                    op.line,
+                    EvaluationFailed,
+                    &format!("Shift amount out of range for {}", op.lexeme),
+                ),
+            },
+            _ => self.error(
+                op.line,
+                EvaluationFailed,
+                &format!("Operands of {} must be integers", op.lexeme),
+            ),
+        }
+    }
+
+    fn comparison(
+        &self,
+        op: &Token,
+        l: Value,
+        r: Value,
+        int_cmp: fn(&i64, &i64) -> bool,
+        float_cmp: fn(&f64, &f64) -> bool,
+    ) -> Result<Value, CompileError> {
+        match (l, r) {
+            (Value::Int(l), Value::Int(r)) => Ok(Value::Bool(int_cmp(&l, &r))),
+            (l, r) => match (l.as_f64(), r.as_f64()) {
+                (Some(l), Some(r)) => Ok(Value::Bool(float_cmp(&l, &r))),
+                _ => self.error(
+                    op.line,
+                    EvaluationFailed,
+                    &format!("Unsupported types for {}", op.lexeme),
+                ),
+            },
+        }
     }
 
     fn error<T>(
-        self: &Self,
+        &self,
         line: usize,
         error_type: CompileErrorType,
         msg: &str,
     ) -> Result<T, CompileError> {
         Err(CompileError {
             err: error_type,
-            line: line,
+            line,
             text: if msg.is_empty() {
                 None
             } else {
                 Some(msg.to_string())
             },
+            at: None,
         })
     }
 }
 
+// The value a `catch` block receives for an error: thrown values as they are and
+// runtime failures as error objects. Other errors can't happen while running.
+fn caught(e: &CompileError) -> Option<Value> {
+    match e.err {
+        Thrown(ref v) => Some(v.clone()),
+        EvaluationFailed => Some(Value::error(
+            e.text.clone().unwrap_or_default(),
+            e.line,
+            vec![e.line],
+        )),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::super::astprinter;
+    use super::super::parser::Parser;
     use super::*;
 
     use super::super::Scanner;
+    use std::cell::{Cell, RefCell};
+
+    fn eval(source: &str) -> Result<Value, CompileError> {
+        eval_in(&mut Interpreter::new(), source)
+    }
+
+    fn eval_in(interpreter: &mut Interpreter, source: &str) -> Result<Value, CompileError> {
+        let mut s = Scanner::new(source);
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        let stmts = p.parse().unwrap();
+
+        // Runs all statements but the last one, which must be an expression to evaluate.
+        interpreter.exec(&stmts[..stmts.len() - 1])?;
+        match &stmts[stmts.len() - 1] {
+            Stmt::Expression(e) => interpreter.evaluate(e),
+            _ => panic!("wrong type"),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("1+2*3"), Ok(Value::Int(7)));
+        assert_eq!(eval("(1+2)*3"), Ok(Value::Int(9)));
+        assert_eq!(eval("-4/2"), Ok(Value::Int(-2)));
+        assert_eq!(eval("\"a\"+\"b\""), Ok(Value::Str("ab".to_string())));
+        assert_eq!(eval("1<2"), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval("\"${1}+${1.5} is ${1+1.5}\\n\""),
+            Ok(Value::Str("1+1.5 is 2.5\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn integers() {
+        assert!(matches!(eval("7/2"), Ok(Value::Int(3))));
+        assert!(matches!(eval("-7%3"), Ok(Value::Int(-1))));
+        assert!(matches!(eval("7/2.0"), Ok(Value::Number(n)) if n == 3.5));
+        assert!(matches!(eval("7.5%2"), Ok(Value::Number(n)) if n == 1.5));
+        assert!(matches!(eval("2-0.5"), Ok(Value::Number(n)) if n == 1.5));
+        assert_eq!(
+            eval("9007199254740993 - 1"),
+            Ok(Value::Int(9007199254740992))
+        );
+        assert_eq!(eval("3 == 3.0"), Ok(Value::Bool(true)));
+        assert_eq!(eval("2 < 2.5"), Ok(Value::Bool(true)));
+        assert_eq!(eval("0x10 * 0b10").unwrap().to_string(), "32");
+
+        for overflow in &["9223372036854775807 + 1", "-(-9223372036854775807 - 1)"] {
+            let e = eval(overflow).unwrap_err();
+            assert_eq!(e.err, EvaluationFailed);
+        }
+        assert_eq!(
+            eval("1 / 0").unwrap_err().text,
+            Some("Division by zero".to_string())
+        );
+        assert_eq!(eval("1 / 0.0"), Ok(Value::Number(f64::INFINITY)));
+        assert_eq!(eval("-0.0").unwrap().to_string(), "-0");
+        assert_eq!(eval("1 / -0.0").unwrap().to_string(), "-Infinity");
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(eval("2 ** 10"), Ok(Value::Int(1024)));
+        assert_eq!(eval("-2 ** 2"), Ok(Value::Int(-4)));
+        assert_eq!(eval("2 ** -1"), Ok(Value::Number(0.5)));
+        assert_eq!(eval("4 ** 0.5"), Ok(Value::Number(2.)));
+        assert_eq!(eval("6 & 3 | 8 ^ 1"), Ok(Value::Int(11)));
+        assert_eq!(eval("~0"), Ok(Value::Int(-1)));
+        assert_eq!(eval("1 << 4 >> 2"), Ok(Value::Int(4)));
+        assert_eq!(eval("-8 >> 1"), Ok(Value::Int(-4)));
+
+        for bad in &["2 ** 64", "1 << 64", "1 << -1", "1.5 & 1", "~1.0"] {
+            assert_eq!(eval(bad).unwrap_err().err, EvaluationFailed);
+        }
+    }
+
+    #[test]
+    fn lengths() {
+        let too_long = Some("String would be longer than the limit of 16777216".to_string());
+        let doubling = "var s = \"ab\"; while (true) { var _ = s += s; } s";
+        assert_eq!(eval(doubling).unwrap_err().text, too_long);
+        let doubling = "var s = \"ab\"; while (true) { var _ = s = \"${s}${s}\"; } s";
+        assert_eq!(eval(doubling).unwrap_err().text, too_long);
+        assert_eq!(
+            eval("var s = \"ab\"; try { while (true) { var _ = s += s; } } catch (e) {} s.len()"),
+            Ok(Value::Int(1 << 24))
+        );
+    }
+
+    #[test]
+    fn conditional_and_comma() {
+        assert_eq!(
+            eval("1 < 2 ? \"yes\" : \"no\""),
+            Ok(Value::Str("yes".to_string()))
+        );
+        assert_eq!(eval("1 > 2 ? 1 : 0 > 1 ? 2 : 3"), Ok(Value::Int(3)));
+        // Only the selected branch is evaluated.
+        assert_eq!(
+            eval("var a = 0; 1 ? a += 1 : (a += 10); a"),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(eval("1 > 2 ? undefined : 2"), Ok(Value::Int(2)));
+
+        assert_eq!(eval("var a = 1; a += 1, a * 10"), Ok(Value::Int(20)));
+        assert_eq!(eval("var a; a = 1, 2; a"), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval("var a = 1; var b = a + 1; b"), Ok(Value::Int(2)));
+        assert_eq!(eval("var a; a"), Ok(Value::Nil));
+        assert_eq!(eval("var a = 1; a = a + 1; a"), Ok(Value::Int(2)));
+        assert_eq!(
+            eval("var a = 10; a += 5; a -= 1; a *= 3; a /= 2"),
+            Ok(Value::Int(21))
+        );
+        assert_eq!(
+            eval("var s = \"a\"; s += \"b\""),
+            Ok(Value::Str("ab".to_string()))
+        );
+        assert_eq!(eval("var a; var b; a = b = 2; a"), Ok(Value::Int(2)));
+
+        let e = eval("a = 1").unwrap_err();
+        assert_eq!(e.text, Some("Undefined variable 'a'".to_string()));
+        assert!(eval("b += 1").is_err());
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            eval("[1, \"a\", [2.5]]").unwrap().to_string(),
+            "[1, \"a\", [2.5]]"
+        );
+        assert_eq!(
+            eval("var xs = [1, 2, 3]; xs[0] + xs[-1]"),
+            Ok(Value::Int(4))
+        );
+        assert_eq!(
+            eval("var xs = [1, 2]; xs[1] = 5; xs[-2] += 10; xs")
+                .unwrap()
+                .to_string(),
+            "[11, 5]"
+        );
+        assert_eq!(
+            eval("var xs = []; var ys = xs; ys.push(1); xs.push(2); xs.len()"),
+            Ok(Value::Int(2))
+        );
+        assert_eq!(eval("[[1, 2], [3]][0][1]"), Ok(Value::Int(2)));
+        assert_eq!(eval("[1, [2]] == [1, [2]]"), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval("var xs = [0]; xs[0] = xs; xs").unwrap().to_string(),
+            "[[...]]"
+        );
+        assert_eq!(
+            eval("var a = [0]; a[0] = a; var b = [0]; b[0] = b; a == b"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval("var a = [0, 1]; a[0] = a; var b = [0, 2]; b[0] = b; a == b"),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            eval("var xs = [1, 2, 3, 4]; xs.remove(1); xs.insert(0, xs.pop()); xs.slice(1)")
+                .unwrap()
+                .to_string(),
+            "[1, 3]"
+        );
+
+        let e = eval("var xs = [1, 2];\nxs[2]").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.text, Some("List index 2 out of range".to_string()));
+        assert!(eval("[1][-2] = 0").is_err());
+        assert!(eval("[1][\"0\"]").is_err());
+        assert!(eval("1[0]").is_err());
+        assert!(eval("[].pop()").is_err());
+        assert!(eval("[].len").is_err());
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            eval("var a = 1; { var a = 2; a += 1; } a"),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            eval("var a = 1; { a = 2; { a += 1 } } a"),
+            Ok(Value::Int(3))
+        );
+        assert!(eval("{ var b = 1; } b").is_err());
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            eval("var r; if (0 > 1) r = 1; else if (1 > 2) r = 2; else r = 3; r"),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            eval("var n = 0; while (n < 3) n += 1; n"),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            eval(
+                "var s = 0;
+                for (var i = 0; i < 10; i += 1) {
+                    if (i == 5) break;
+                    if (i % 2 == 0) continue;
+                    s += i;
+                }
+                s"
+            ),
+            Ok(Value::Int(4))
+        );
+        assert_eq!(
+            eval("var i = 0; while (1) { { { i += 1; if (i == 3) { break; } } } } i"),
+            Ok(Value::Int(3))
+        );
+        assert!(eval("for (var i = 0; i < 1; i += 1) {} i").is_err());
+    }
+
+    #[test]
+    fn for_in() {
+        assert_eq!(
+            eval("var s = 0; for (var x in [1, 2, 3]) s += x; s"),
+            Ok(Value::Int(6))
+        );
+        assert_eq!(
+            eval("var ks = \"\"; for (var k in {\"b\": 1, \"a\": 2}) ks += k; ks"),
+            Ok(Value::Str("ba".to_string()))
+        );
+        assert_eq!(
+            eval("var cs = []; for (var c in \"h\\u{e9}!\") cs.push(c); cs")
+                .unwrap()
+                .to_string(),
+            "[\"h\", \"\u{e9}\", \"!\"]"
+        );
+        // `break` only leaves the innermost loop.
+        assert_eq!(
+            eval(
+                "var n = 0;
+                for (var i in [1, 2, 3]) for (var j in [1, 2, 3]) { if (j == 2) break; n += 1; }
+                n"
+            ),
+            Ok(Value::Int(3))
+        );
+        // The loop walks the list as it was when the loop started.
+        assert_eq!(
+            eval("var xs = [1, 2]; for (var x in xs) xs.push(x); xs.len()"),
+            Ok(Value::Int(4))
+        );
+
+        assert!(eval("for (var x in [1]) {} x").is_err());
+        let e = eval("\nfor (var x in 1) {}\n0").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.text, Some("Can't iterate over int".to_string()));
+    }
+
+    #[test]
+    fn exceptions() {
+        assert_eq!(
+            eval("var r; try { r = 1 / 0; } catch (e) { r = e.message; } r"),
+            Ok(Value::Str("Division by zero".to_string()))
+        );
+        assert_eq!(
+            eval("var r; try { throw \"bad\"; } catch (e) { r = e; } r"),
+            Ok(Value::Str("bad".to_string()))
+        );
+        assert_eq!(
+            eval("var l; try {\n\n[][0] } catch (e) { l = e.line } l"),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            eval("var log = []; try { log.push(1); throw 2; } catch (e) { log.push(e); } finally { log.push(3); } log")
+                .unwrap()
+                .to_string(),
+            "[1, 2, 3]"
+        );
+        assert_eq!(
+            eval("var log = []; try { try { throw 1; } finally { log.push(0); } } catch (e) { log.push(e); } log")
+                .unwrap()
+                .to_string(),
+            "[0, 1]"
+        );
+        assert_eq!(
+            eval("var n = 0; while (1) { try { break; } finally { n += 1; } } n"),
+            Ok(Value::Int(1))
+        );
+        // An error raised in `finally` replaces the one from the try block.
+        assert_eq!(
+            eval("var r; try { try { throw 1; } finally { throw 2; } } catch (e) { r = e; } r"),
+            Ok(Value::Int(2))
+        );
+
+        assert_eq!(
+            eval("throw [1]; 0").unwrap_err().err,
+            Thrown(Value::list(vec![Value::Int(1)]))
+        );
+        assert!(eval("try { throw 1; } catch (e) {} e").is_err());
+
+        let e = eval("try { 1 / 0 } catch (e) {\nthrow e } 0").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(
+            e.to_string(),
+            "Uncaught Error: Division by zero\n[line 2]\n    raised at line 1\n    rethrown at line 2"
+        );
+    }
+
+    #[test]
+    fn imports() {
+        let dir = std::env::temp_dir().join(format!("lox-imports-{}", std::process::id()));
+        let files = [
+            (
+                "util.lox",
+                "export var answer = 42; var hidden = 1; export var log = [];",
+            ),
+            (
+                "lib/double.lox",
+                "import { answer } from \"../util.lox\"; export var doubled = answer * 2;",
+            ),
+            (
+                "lib/push.lox",
+                "import \"../util.lox\" as u; u.log.push(1);",
+            ),
+            ("cycle/a.lox", "import \"b.lox\";"),
+            ("cycle/b.lox", "import \"a.lox\";"),
+            ("broken.lox", "1 +"),
+            ("vendor/pkg.lox", "export var name = \"pkg\";"),
+            ("lib/globals.lox", "export var digits = str(123).len();"),
+        ];
+        for (name, source) in &files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+
+        let run = |source: &str| {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_file(&dir.join("main.lox"));
+            interpreter.set_search_path(vec![dir.join("vendor")]);
+            eval_in(&mut interpreter, source)
+        };
+
+        assert_eq!(
+            run("import \"util.lox\" as u; import { doubled as d } from \"lib/double.lox\"; u.answer + d"),
+            Ok(Value::Int(126))
+        );
+        // Each module is evaluated once and shares its values with every importer.
+        assert_eq!(
+            run("import \"lib/push.lox\"; import \"lib/push.lox\"; import { log } from \"util.lox\"; log.len()"),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            run("import { name } from \"pkg.lox\"; name"),
+            Ok(Value::Str("pkg".to_string()))
+        );
+
+        assert_eq!(
+            run("import { digits } from \"lib/globals.lox\"; digits"),
+            Ok(Value::Int(3))
+        );
+
+        let e = run("import \"cycle/a.lox\"; 0").unwrap_err();
+        let (a, b) = (dir.join("cycle/a.lox"), dir.join("cycle/b.lox"));
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "In {0}, line 1: In {1}, line 1: Import cycle: {0} -> {1} -> {0}\n[line 1]",
+                a.display(),
+                b.display()
+            )
+        );
+        assert!(run("import { hidden } from \"util.lox\"; 0").is_err());
+        assert!(run("import \"util.lox\" as u; u.hidden").is_err());
+        assert!(run("import \"missing.lox\"; 0").is_err());
+        let e = run("\nimport \"broken.lox\"; 0").unwrap_err();
+        let broken = dir.join("broken.lox");
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "In {}, line 1: Expect expression.\n[line 2]",
+                broken.display()
+            )
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Logs each statement as `file:line@depth`, along with the value of
+    // `a` in the outermost file once it's inside a module.
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Debugger for Recorder {
+        fn before_statement(
+            &mut self,
+            interpreter: &mut Interpreter,
+            depth: usize,
+            line: usize,
+        ) -> Result<(), CompileError> {
+            let file = interpreter.file_at(depth).unwrap().file_name().unwrap();
+            let mut log = self.0.borrow_mut();
+            log.push(format!("{}:{}@{}", file.to_string_lossy(), line, depth));
+            if depth == 1 {
+                log.push(interpreter.evaluate_at(0, "a * 10")?.to_string());
+            }
+            if line == 5 {
+                return interpreter.error(line, EvaluationFailed, "Stopped");
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn debugger() {
+        let dir = std::env::temp_dir().join(format!("lox-debugger-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.lox"), "var a = 2;\nexport var b = a;").unwrap();
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(&dir.join("main.lox"));
+        interpreter.set_output(Box::new(Shared(output.clone())));
+        interpreter.set_debugger(Box::new(Recorder(log.clone())));
+        let e = eval_in(
+            &mut interpreter,
+            "var a = 1;\nimport \"lib.lox\" as l;\n{\n  a + l.b;\n  a;\n}\n0",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                "main.lox:1@0",
+                "main.lox:2@0",
+                "lib.lox:1@1",
+                "10",
+                "lib.lox:2@1",
+                "10",
+                "main.lox:4@0",
+                "main.lox:5@0",
+            ]
+        );
+        assert_eq!(e.text, Some("Stopped".to_string()));
+        assert_eq!(String::from_utf8(output.borrow().clone()).unwrap(), "3\n");
+        // The importer's scopes are back in place after the import.
+        assert_eq!(interpreter.scopes_at(0)[0].get("a"), Some(&Value::Int(1)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Output that the test can still read after handing it over.
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn builtin_modules() {
+        assert_eq!(
+            eval("import \"math\" as math; math.sqrt(16) + math.floor(2.7)"),
+            Ok(Value::Number(6.))
+        );
+        assert_eq!(
+            eval("import { max, PI } from \"math\"; var m = max; m(1, PI) == PI"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval("import \"math\" as a; import \"math\" as b; a.seed(7); var x = a.random(); b.seed(7); x == b.random()"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval("import \"math\" as math; math.sqrt")
+                .unwrap()
+                .to_string(),
+            "<native fn sqrt>"
+        );
+
+        let e = eval("import \"math\" as math;\nmath.sqrt(\n\"x\")").unwrap_err();
+        assert_eq!(e.line, 3);
+        assert_eq!(
+            e.text,
+            Some("sqrt() expects numbers, not string".to_string())
+        );
+        assert!(eval("import \"math\" as math; math.nope(1)").is_err());
+        assert_eq!(
+            eval("import \"json\" as json; var v = json.parse(\"{\\\"a\\\": [1, 2.5]}\"); v[\"a\"][1] = \"x\"; json.stringify(v)"),
+            Ok(Value::Str("{\"a\":[1,\"x\"]}".to_string()))
+        );
+        assert!(eval("import \"json\" as json; json.stringify(json)").is_err());
+        assert_eq!(
+            eval("var x = 1; x()").unwrap_err().text,
+            Some("Can't call int".to_string())
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let s = |s: &str| Ok(Value::Str(s.to_string()));
+        assert_eq!(eval("\"héllo\"[1] + \"héllo\"[-1]"), s("éo"));
+        assert_eq!(eval("\"a b c\".split(\" \").len()"), Ok(Value::Int(3)));
+        assert_eq!(eval("\", \".join([1, \"x\", [2]])"), s("1, x, [2]"));
+        assert_eq!(eval("\"ünï\".upper().substring(1)"), s("NÏ"));
+        assert_eq!(eval("str(1.5) + str(1 < 0)"), s("1.5false"));
+        assert_eq!(eval("num(\"2\") + num(\" 0.5 \")"), Ok(Value::Number(2.5)));
+        assert_eq!(eval("var n = num; n(str(-3))"), Ok(Value::Int(-3)));
+
+        let e = eval("var x = 1\nnum(\"12px\")").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.text, Some("Invalid number \"12px\"".to_string()));
+        assert_eq!(
+            eval("\"abc\"[10]").unwrap_err().text,
+            Some("String index 10 out of range".to_string())
+        );
+        assert_eq!(
+            eval("\"abc\"[nil]").unwrap_err().text,
+            Some("String index must be an int, not nil".to_string())
+        );
+        assert!(eval("\"abc\".upper(1)").is_err());
+    }
+
+    #[test]
+    fn capabilities() {
+        let mut sandboxed = Interpreter::with_capabilities(Capabilities::none());
+        let e = eval_in(&mut sandboxed, "var x = 1\nreadFile(\"/etc/passwd\")").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(
+            e.text,
+            Some("readFile() needs file access, which is disabled".to_string())
+        );
+        assert!(eval_in(&mut sandboxed, "env(\"HOME\")").is_err());
+        assert_eq!(
+            eval_in(&mut sandboxed, "str(2)"),
+            Ok(Value::Str("2".to_string()))
+        );
+        let e = eval_in(&mut sandboxed, "import \"/etc/passwd\" as p; 0").unwrap_err();
+        assert_eq!(
+            e.text,
+            Some("Importing \"/etc/passwd\" needs file access, which is disabled".to_string())
+        );
+        assert_eq!(
+            eval_in(&mut sandboxed, "import \"math\" as m; m.abs(-1)"),
+            Ok(Value::Int(1))
+        );
+
+        assert_eq!(eval("exists(\".\")"), Ok(Value::Bool(true)));
+    }
+
+    // Only moves when told to, or when a script sleeps.
+    struct FakeClock {
+        time: Cell<f64>,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> f64 {
+            self.time.get()
+        }
+
+        fn monotonic(&self) -> f64 {
+            self.time.get()
+        }
+
+        fn sleep(&self, seconds: f64) {
+            self.time.set(self.time.get() + seconds);
+        }
+    }
+
+    #[test]
+    fn fake_clock() {
+        let clock = Rc::new(FakeClock {
+            time: Cell::new(1e9),
+        });
+        let mut interpreter = Interpreter::new();
+        interpreter.set_clock(clock.clone());
+
+        let source = "import \"time\" as time; var start = clock(); time.sleep(1500); [clock() - start, time.format(time.now())]";
+        assert_eq!(
+            eval_in(&mut interpreter, source).unwrap().to_string(),
+            "[1.5, \"2001-09-09T01:46:41.500Z\"]"
+        );
+        clock.time.set(0.0);
+        assert_eq!(
+            eval_in(
+                &mut interpreter,
+                "import \"time\" as time; time.now() == time.parse(\"1970-01-01T00:00Z\")"
+            ),
+            Ok(Value::Bool(true))
+        );
+        // A new clock reaches modules imported before, and leaves globals alone.
+        eval_in(&mut interpreter, "var str = 1; import \"time\" as t; 0").unwrap();
+        interpreter.set_clock(Rc::new(FakeClock {
+            time: Cell::new(60.0),
+        }));
+        assert_eq!(
+            eval_in(&mut interpreter, "[str, t.now(), clock()]")
+                .unwrap()
+                .to_string(),
+            "[1, 60, 60]"
+        );
+        assert!(eval_in(&mut interpreter, "import \"time\" as time; time.sleep(-1)").is_err());
+        assert!(eval_in(
+            &mut interpreter,
+            "import \"time\" as time; time.parse(\"soon\")"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn maps() {
+        assert_eq!(
+            eval("var m = {\"b\": 1, \"a\": [2], 3: {}}; m")
+                .unwrap()
+                .to_string(),
+            "{\"b\": 1, \"a\": [2], 3: {}}"
+        );
+        assert_eq!(
+            eval("var m = {\"x\": 1}; m[\"x\"] += 1; m[\"y\"] = 5; m[\"x\"] * m[\"y\"]"),
+            Ok(Value::Int(10))
+        );
+        assert_eq!(
+            eval("var m = {1: \"a\"}; m[1.0] = \"b\"; m.len()"),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            eval("var m = {\"z\": 0, \"y\": 1}; m.remove(\"z\"); m[\"z\"] = 2; m.keys()")
+                .unwrap()
+                .to_string(),
+            "[\"y\", \"z\"]"
+        );
+        assert_eq!(eval("({1: 2} == {1.0: 2})"), Ok(Value::Bool(true)));
+        assert_eq!(eval("{2: 1, 1: 2} == {1: 2, 2: 1}"), Ok(Value::Bool(true)));
+        assert_eq!(eval("{1: [1]}.has(1)"), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval("var m = {}; m[0] = m; m").unwrap().to_string(),
+            "{0: {...}}"
+        );
+        assert_eq!(
+            eval("var a = {}; a[0] = a; var b = {}; b[0] = b; a == b"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval("var a = {1: 1}; a[0] = [a]; var b = {1: 2}; b[0] = [b]; a == b"),
+            Ok(Value::Bool(false))
+        );
+
+        let e = eval("var m = {};\nm[\"k\"]").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.text, Some("Key \"k\" not found".to_string()));
+        assert!(eval("({[]: 1})").is_err());
+        assert!(eval("var m = {}; m[0.0 / 0.0] = 1").is_err());
+        assert!(eval("({})[0] += 1").is_err());
+    }
+
+    #[test]
+    fn type_errors() {
+        let e = eval("1+\"a\"").unwrap_err();
+        assert_eq!(e.err, EvaluationFailed);
+        assert_eq!(e.line, 1);
+    }
 }
@@ -0,0 +1,494 @@
+// A Language Server Protocol server, run by `lox-rust lsp` over stdio.
+// Documents are re-scanned, parsed and resolved on every request. That's
+// cheap at the size of Lox scripts, and keeps no state besides their text.
+
+use super::errors::CompileError;
+use super::parser::Parser;
+use super::protocol::{self, as_str, as_usize, get, int, object, string};
+use super::resolver::{self, Resolution, SymbolKind};
+use super::scanner::Scanner;
+use super::stdlib::{self, Capabilities, ClockCell, SystemClock};
+use super::tokens::TokenType;
+use super::value::Value;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+// Keywords of the statements and literals the parser accepts.
+const KEYWORDS: &[&str] = &[
+    "break", "catch", "continue", "else", "export", "false", "finally", "for", "if", "import",
+    "in", "nil", "throw", "true", "try", "var", "while",
+];
+
+// Error codes defined by JSON-RPC.
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Kinds of symbols and completions in the protocol.
+const SYMBOL_MODULE: usize = 2;
+const SYMBOL_VARIABLE: usize = 13;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_MODULE: usize = 9;
+const COMPLETION_KEYWORD: usize = 14;
+
+// Serves requests from `input` until the client says to exit, and returns the
+// exit code, which is non-zero if it didn't ask to shut down first.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<i32> {
+    let clock: ClockCell = Rc::new(RefCell::new(Rc::new(SystemClock::new())));
+    let mut builtins: Vec<String> = stdlib::globals(Capabilities::all(), &clock)
+        .into_keys()
+        .collect();
+    builtins.sort();
+
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        builtins,
+        shut_down: false,
+    };
+    while let Some(message) = protocol::read_message(&mut input)? {
+        if as_str(&get(&message, "method")) == Some("exit") {
+            break;
+        }
+        server.handle(&message)?;
+    }
+    Ok(if server.shut_down { 0 } else { 1 })
+}
+
+struct Server<W: Write> {
+    output: W,
+    // Text of every open document by URI.
+    documents: HashMap<String, String>,
+    builtins: Vec<String>,
+    shut_down: bool,
+}
+
+// A failed request, answered with a JSON-RPC error.
+struct Failure(i64, String);
+
+impl<W: Write> Server<W> {
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        let id = get(message, "id");
+        let method = get(message, "method");
+        let method = as_str(&method).unwrap_or("");
+        let params = get(message, "params");
+
+        let result = match method {
+            "initialize" => Ok(initialize()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Nil)
+            }
+            "textDocument/didOpen" => {
+                let document = get(&params, "textDocument");
+                let text = as_str(&get(&document, "text")).unwrap_or("").to_string();
+                return self.update(&document, Some(text));
+            }
+            "textDocument/didChange" => {
+                // Changes are always the whole text, as asked for in `initialize`.
+                let text = match &get(&params, "contentChanges") {
+                    Value::List(changes) => changes
+                        .borrow()
+                        .last()
+                        .and_then(|change| as_str(&get(change, "text")).map(str::to_string)),
+                    _ => None,
+                };
+                return self.update(&get(&params, "textDocument"), text);
+            }
+            "textDocument/didClose" => return self.update(&get(&params, "textDocument"), None),
+            "textDocument/definition" => self.definition(&params),
+            "textDocument/references" => self.references(&params),
+            "textDocument/hover" => self.hover(&params),
+            "textDocument/documentSymbol" => self.symbols(&params),
+            "textDocument/completion" => self.completion(&params),
+            "textDocument/rename" => self.rename(&params),
+            _ => Err(Failure(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{}'", method),
+            )),
+        };
+
+        // Notifications have no id and get no response.
+        if id == Value::Nil {
+            return Ok(());
+        }
+        let response = match result {
+            Ok(result) => object(vec![
+                ("jsonrpc", string("2.0")),
+                ("id", id),
+                ("result", result),
+            ]),
+            Err(Failure(code, message)) => object(vec![
+                ("jsonrpc", string("2.0")),
+                ("id", id),
+                (
+                    "error",
+                    object(vec![
+                        ("code", Value::Int(code)),
+                        ("message", string(&message)),
+                    ]),
+                ),
+            ]),
+        };
+        protocol::write_message(&mut self.output, &response)
+    }
+
+    // Stores the new text of a document, or forgets it when there's none,
+    // and publishes its diagnostics.
+    fn update(&mut self, document: &Value, text: Option<String>) -> io::Result<()> {
+        let uri = as_str(&get(document, "uri")).unwrap_or("").to_string();
+        let diagnostics = match &text {
+            Some(text) => match analyze(text) {
+                Err(e) => vec![diagnostic(text, &e)],
+                Ok(_) => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+        match text {
+            Some(text) => self.documents.insert(uri.clone(), text),
+            None => self.documents.remove(&uri),
+        };
+
+        let notification = object(vec![
+            ("jsonrpc", string("2.0")),
+            ("method", string("textDocument/publishDiagnostics")),
+            (
+                "params",
+                object(vec![
+                    ("uri", string(&uri)),
+                    ("diagnostics", Value::list(diagnostics)),
+                ]),
+            ),
+        ]);
+        protocol::write_message(&mut self.output, &notification)
+    }
+
+    // The text of the document `params` refers to, the byte offset of the
+    // position in it and its resolution. Documents that don't parse have none.
+    fn locate(&self, params: &Value) -> Result<(&str, usize, Option<Resolution>), Failure> {
+        let uri = get(&get(params, "textDocument"), "uri");
+        let text = as_str(&uri)
+            .and_then(|uri| self.documents.get(uri))
+            .ok_or_else(|| Failure(INVALID_PARAMS, "Unknown document".to_string()))?;
+        let offset = offset(text, &get(params, "position"))
+            .ok_or_else(|| Failure(INVALID_PARAMS, "Invalid position".to_string()))?;
+        Ok((text, offset, analyze(text).ok()))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, Failure> {
+        let (text, offset, resolution) = self.locate(params)?;
+        let uri = get(&get(params, "textDocument"), "uri");
+        Ok(resolution
+            .as_ref()
+            .and_then(|r| r.symbol_at(offset))
+            .map_or(Value::Nil, |symbol| {
+                location(&uri, text, symbol.declaration, &symbol.name)
+            }))
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, Failure> {
+        let (text, offset, resolution) = self.locate(params)?;
+        let uri = get(&get(params, "textDocument"), "uri");
+        let include_declaration = get(&get(params, "context"), "includeDeclaration").is_truthy();
+        let symbol = match resolution.as_ref().and_then(|r| r.symbol_at(offset)) {
+            Some(symbol) => symbol,
+            None => return Ok(Value::list(Vec::new())),
+        };
+
+        let locations = symbol
+            .occurrences()
+            .skip(if include_declaration { 0 } else { 1 })
+            .map(|start| location(&uri, text, start, &symbol.name))
+            .collect();
+        Ok(Value::list(locations))
+    }
+
+    // Shows the line a variable is declared on, or says a name is built in.
+    fn hover(&self, params: &Value) -> Result<Value, Failure> {
+        let (text, offset, resolution) = self.locate(params)?;
+        let resolution = match resolution {
+            Some(resolution) => resolution,
+            None => return Ok(Value::Nil),
+        };
+
+        let (contents, start, len) = if let Some(symbol) = resolution.symbol_at(offset) {
+            let line_start = text[..symbol.declaration].rfind('\n').map_or(0, |i| i + 1);
+            let line = text[line_start..].lines().next().unwrap_or("").trim();
+            let start = symbol.occurrence_at(offset).unwrap_or(symbol.declaration);
+            (format!("```lox\n{}\n```", line), start, symbol.name.len())
+        } else {
+            let builtin = resolution.unresolved.iter().find(|(name, start)| {
+                (*start..=start + name.len()).contains(&offset) && self.builtins.contains(name)
+            });
+            match builtin {
+                Some((name, start)) => {
+                    (format!("Built-in function `{}`", name), *start, name.len())
+                }
+                None => return Ok(Value::Nil),
+            }
+        };
+
+        Ok(object(vec![
+            (
+                "contents",
+                object(vec![
+                    ("kind", string("markdown")),
+                    ("value", string(&contents)),
+                ]),
+            ),
+            ("range", range(text, start, start + len)),
+        ]))
+    }
+
+    fn symbols(&self, params: &Value) -> Result<Value, Failure> {
+        let uri = get(&get(params, "textDocument"), "uri");
+        let text = as_str(&uri)
+            .and_then(|uri| self.documents.get(uri))
+            .ok_or_else(|| Failure(INVALID_PARAMS, "Unknown document".to_string()))?;
+        let resolution = analyze(text).unwrap_or_default();
+
+        let symbols = resolution
+            .symbols
+            .iter()
+            .map(|symbol| {
+                let kind = match symbol.kind {
+                    SymbolKind::Variable => SYMBOL_VARIABLE,
+                    SymbolKind::Module => SYMBOL_MODULE,
+                };
+                let name_range = range(
+                    text,
+                    symbol.declaration,
+                    symbol.declaration + symbol.name.len(),
+                );
+                object(vec![
+                    ("name", string(&symbol.name)),
+                    ("kind", int(kind)),
+                    ("range", name_range.clone()),
+                    ("selectionRange", name_range),
+                ])
+            })
+            .collect();
+        Ok(Value::list(symbols))
+    }
+
+    // Names in scope at the position, then built-in functions and keywords.
+    fn completion(&self, params: &Value) -> Result<Value, Failure> {
+        let (_, offset, resolution) = self.locate(params)?;
+        let resolution = resolution.unwrap_or_default();
+
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+        let mut add = |label: &str, kind: usize| {
+            if seen.insert(label.to_string()) {
+                items.push(object(vec![("label", string(label)), ("kind", int(kind))]));
+            }
+        };
+        // Innermost first, so shadowed names are listed once.
+        for symbol in resolution
+            .visible_at(offset)
+            .collect::<Vec<_>>()
+            .iter()
+            .rev()
+        {
+            let kind = match symbol.kind {
+                SymbolKind::Variable => COMPLETION_VARIABLE,
+                SymbolKind::Module => COMPLETION_MODULE,
+            };
+            add(&symbol.name, kind);
+        }
+        for name in &self.builtins {
+            add(name, COMPLETION_FUNCTION);
+        }
+        for keyword in KEYWORDS {
+            add(keyword, COMPLETION_KEYWORD);
+        }
+        Ok(Value::list(items))
+    }
+
+    fn rename(&self, params: &Value) -> Result<Value, Failure> {
+        let (text, offset, resolution) = self.locate(params)?;
+        let new_name = as_str(&get(params, "newName")).unwrap_or("").to_string();
+        if !is_identifier(&new_name) {
+            return Err(Failure(
+                INVALID_PARAMS,
+                format!("'{}' is not a valid name", new_name),
+            ));
+        }
+        let symbol = match resolution.as_ref().and_then(|r| r.symbol_at(offset)) {
+            Some(symbol) => symbol,
+            None => return Ok(Value::Nil),
+        };
+
+        let edits = symbol
+            .occurrences()
+            .map(|start| {
+                object(vec![
+                    ("range", range(text, start, start + symbol.name.len())),
+                    ("newText", string(&new_name)),
+                ])
+            })
+            .collect();
+        let uri = get(&get(params, "textDocument"), "uri");
+        let uri = as_str(&uri).unwrap_or("");
+        Ok(object(vec![(
+            "changes",
+            object(vec![(uri, Value::list(edits))]),
+        )]))
+    }
+}
+
+fn initialize() -> Value {
+    object(vec![
+        (
+            "capabilities",
+            object(vec![
+                // Documents are synced by sending their full text.
+                ("textDocumentSync", int(1)),
+                ("definitionProvider", Value::Bool(true)),
+                ("referencesProvider", Value::Bool(true)),
+                ("hoverProvider", Value::Bool(true)),
+                ("documentSymbolProvider", Value::Bool(true)),
+                ("completionProvider", object(Vec::new())),
+                ("renameProvider", Value::Bool(true)),
+            ]),
+        ),
+        ("serverInfo", object(vec![("name", string("lox-rust"))])),
+    ])
+}
+
+fn analyze(text: &str) -> Result<Resolution, CompileError> {
+    let mut s = Scanner::new(text);
+    let tokens = s.scan_tokens()?;
+    let mut p = Parser::new(tokens);
+    let stmts = p.parse()?;
+    Ok(resolver::resolve(text, stmts))
+}
+
+// Errors only carry a line, so the whole line is marked.
+fn diagnostic(text: &str, e: &CompileError) -> Value {
+    let line = e.line.saturating_sub(1);
+    let line_text = text.lines().nth(line).unwrap_or("");
+    let message = e.message();
+
+    let position = |character| object(vec![("line", int(line)), ("character", int(character))]);
+    object(vec![
+        (
+            "range",
+            object(vec![
+                ("start", position(0)),
+                ("end", position(line_text.encode_utf16().count())),
+            ]),
+        ),
+        // An error, the most severe kind.
+        ("severity", int(1)),
+        ("source", string("lox")),
+        ("message", string(&message)),
+    ])
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut s = Scanner::new(name);
+    match s.scan_tokens() {
+        Ok(tokens) => {
+            tokens.len() == 2
+                && tokens[0].ttype == TokenType::Identifier
+                && tokens[0].lexeme == name
+        }
+        Err(_) => false,
+    }
+}
+
+fn location(uri: &Value, text: &str, start: usize, name: &str) -> Value {
+    object(vec![
+        ("uri", uri.clone()),
+        ("range", range(text, start, start + name.len())),
+    ])
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    object(vec![
+        ("start", position(text, start)),
+        ("end", position(text, end)),
+    ])
+}
+
+// Positions are a 0-based line and the UTF-16 code units before them on it.
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    object(vec![
+        ("line", int(before.matches('\n').count())),
+        (
+            "character",
+            int(before[line_start..].encode_utf16().count()),
+        ),
+    ])
+}
+
+// The byte offset of a position, None if it's past the end of the text.
+// A character past the end of its line is taken as the end of the line.
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = as_usize(&get(position, "line"))?;
+    let character = as_usize(&get(position, "character"))?;
+
+    let line_start = if line == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, character: usize) -> Value {
+        object(vec![("line", int(line)), ("character", int(character))])
+    }
+
+    #[test]
+    fn positions() {
+        let text = "var a;\nvar \u{1F600} = \"\u{e9}\"; a";
+        assert_eq!(offset(text, &at(0, 4)), Some(4));
+        assert_eq!(offset(text, &at(0, 99)), Some(6));
+        assert_eq!(offset(text, &at(1, 0)), Some(7));
+        // The emoji is two UTF-16 code units but four bytes.
+        assert_eq!(offset(text, &at(1, 6)), Some(15));
+        assert_eq!(offset(text, &at(2, 0)), None);
+
+        for offset in &[0, 4, 7, 11, 15, 19, 21, text.len()] {
+            let p = position(text, *offset);
+            assert_eq!(super::offset(text, &p), Some(*offset));
+        }
+        assert_eq!(position(text, text.len()), at(1, 15));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let text = "var a = 1;\nvar = 2;";
+        let e = analyze(text).unwrap_err();
+        let d = diagnostic(text, &e);
+        assert_eq!(get(&get(&d, "range"), "start"), at(1, 0));
+        assert_eq!(get(&get(&d, "range"), "end"), at(1, 8));
+        assert_eq!(as_str(&get(&d, "message")), Some("Expect variable name."));
+    }
+
+    #[test]
+    fn identifiers() {
+        assert!(is_identifier("total"));
+        assert!(is_identifier("_x1"));
+        for bad in &["", "1x", "var", "a b", "a.b", "\"a\""] {
+            assert!(!is_identifier(bad), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use super::value::Value;
+use std::collections::HashMap;
+
+// `Value` holds floats and mutable containers, so it can't be hashed itself.
+// Keys are converted to this instead, with numbers normalized so that keys
+// that compare equal as values (`1 == 1.0`, `0.0 == -0.0`) hash the same.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Str(String),
+    Int(i64),
+    // Bits of a float that has no exact integer value.
+    Float(u64),
+    Bool(bool),
+    Nil,
+}
+
+impl Key {
+    pub fn new(v: &Value) -> Result<Key, String> {
+        match *v {
+            Value::Str(ref s) => Ok(Key::Str(s.clone())),
+            Value::Int(i) => Ok(Key::Int(i)),
+            Value::Number(n) if n.is_nan() => Err("NaN can't be used as a map key".to_string()),
+            Value::Number(n) if is_integral(n) => Ok(Key::Int(n as i64)),
+            Value::Number(n) => Ok(Key::Float(n.to_bits())),
+            Value::Bool(b) => Ok(Key::Bool(b)),
+            Value::Nil => Ok(Key::Nil),
+            _ => Err(format!("Unhashable map key type {}", v.type_name())),
+        }
+    }
+}
+
+fn is_integral(n: f64) -> bool {
+    n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64
+}
+
+// A hash map that iterates in insertion order. Updating a key keeps its
+// position, removing it shifts the entries after it.
+#[derive(Clone, Debug, Default)]
+pub struct OrderedMap {
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+}
+
+impl OrderedMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, String> {
+        let key = Key::new(key)?;
+        Ok(self.index.get(&key).map(|&i| &self.entries[i].1))
+    }
+
+    pub fn contains(&self, key: &Value) -> Result<bool, String> {
+        Ok(self.index.contains_key(&Key::new(key)?))
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), String> {
+        let hashed = Key::new(&key)?;
+        match self.index.get(&hashed) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(hashed, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, String> {
+        let i = match self.index.remove(&Key::new(key)?) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        let (_, value) = self.entries.remove(i);
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    // Removes every entry, in order.
+    pub fn drain(&mut self) -> impl Iterator<Item = (Value, Value)> + '_ {
+        self.index.clear();
+        self.entries.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut m = OrderedMap::new();
+        for k in &["b", "a", "c"] {
+            m.insert(s(k), Value::Int(0)).unwrap();
+        }
+        m.insert(s("a"), Value::Int(1)).unwrap();
+        assert_eq!(
+            m.keys().cloned().collect::<Vec<_>>(),
+            [s("b"), s("a"), s("c")]
+        );
+
+        assert_eq!(m.remove(&s("b")), Ok(Some(Value::Int(0))));
+        assert_eq!(m.remove(&s("b")), Ok(None));
+        m.insert(s("b"), Value::Int(2)).unwrap();
+        assert_eq!(
+            m.keys().cloned().collect::<Vec<_>>(),
+            [s("a"), s("c"), s("b")]
+        );
+        assert_eq!(m.get(&s("c")), Ok(Some(&Value::Int(0))));
+        assert_eq!(m.values().cloned().collect::<Vec<_>>().len(), 3);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(Key::new(&Value::Number(1.0)), Key::new(&Value::Int(1)));
+        assert_eq!(Key::new(&Value::Number(-0.0)), Key::new(&Value::Int(0)));
+        assert_ne!(Key::new(&Value::Number(1.5)), Key::new(&Value::Int(1)));
+        assert_ne!(Key::new(&s("1")), Key::new(&Value::Int(1)));
+        assert!(Key::new(&Value::Number(f64::NAN)).is_err());
+        assert!(Key::new(&Value::list(vec![])).is_err());
+
+        let mut m = OrderedMap::new();
+        m.insert(Value::Number(2.0), s("x")).unwrap();
+        m.insert(Value::Int(2), s("y")).unwrap();
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.get(&Value::Int(2)), Ok(Some(&s("y"))));
+    }
+}
@@ -0,0 +1,462 @@
+use super::map::OrderedMap;
+use super::value::Value;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+// Longest string, in bytes, and longest list that scripts can build. Going past
+// it is a runtime error, rather than an allocation that may abort the process.
+pub const MAX_LENGTH: usize = 1 << 24;
+
+// Built-in methods, called as `value.name(args)`. Errors are reported by the
+// interpreter at the line of the call.
+pub fn call(obj: &Value, name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match obj {
+        Value::List(items) => list_method(items, name, args),
+        Value::Map(entries) => map_method(entries, name, args),
+        Value::Str(s) => string_method(s, name, args),
+        _ => Err(format!(
+            "Undefined method '{}' on {}",
+            name,
+            obj.type_name()
+        )),
+    }
+}
+
+// Built-in fields, read as `value.name`.
+pub fn field(obj: &Value, name: &str) -> Result<Value, String> {
+    match (obj, name) {
+        (Value::Error(e), "message") => Ok(Value::Str(e.message.clone())),
+        (Value::Error(e), "line") => Ok(Value::Int(e.line as i64)),
+        (Value::Error(e), "trace") => Ok(Value::list(
+            e.trace
+                .iter()
+                .map(|&line| Value::Int(line as i64))
+                .collect(),
+        )),
+        (Value::Module(m), _) => m
+            .exports
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Module {} has no export '{}'", m.path.display(), name)),
+        _ => Err(format!("Undefined field '{}' on {}", name, obj.type_name())),
+    }
+}
+
+fn list_method(
+    items: &Rc<RefCell<Vec<Value>>>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match name {
+        "len" => {
+            let [] = expect_args(name, args)?;
+            Ok(Value::Int(items.borrow().len() as i64))
+        }
+        "push" => {
+            let [v] = expect_args(name, args)?;
+            check_length("List", items.borrow().len() + 1)?;
+            items.borrow_mut().push(v);
+            Ok(Value::Nil)
+        }
+        "pop" => {
+            let [] = expect_args(name, args)?;
+            items
+                .borrow_mut()
+                .pop()
+                .ok_or_else(|| "Can't pop from an empty list".to_string())
+        }
+        "insert" => {
+            let [i, v] = expect_args(name, args)?;
+            let mut items = items.borrow_mut();
+            check_length("List", items.len() + 1)?;
+            // Inserting right after the last element appends.
+            let len = items.len();
+            let i = if as_int("List", &i)? == len as i64 {
+                len
+            } else {
+                index("List", &i, len)?
+            };
+            items.insert(i, v);
+            Ok(Value::Nil)
+        }
+        "remove" => {
+            let [i] = expect_args(name, args)?;
+            let mut items = items.borrow_mut();
+            let i = index("List", &i, items.len())?;
+            Ok(items.remove(i))
+        }
+        "slice" => {
+            let items = items.borrow();
+            let (start, end) = match args.as_slice() {
+                [start] => (clamp("List", start, items.len())?, items.len()),
+                [start, end] => (
+                    clamp("List", start, items.len())?,
+                    clamp("List", end, items.len())?,
+                ),
+                _ => {
+                    return Err(format!(
+                        "slice() expects 1 or 2 arguments but got {}",
+                        args.len()
+                    ))
+                }
+            };
+            let slice = if start < end {
+                items[start..end].to_vec()
+            } else {
+                Vec::new()
+            };
+            Ok(Value::list(slice))
+        }
+        _ => Err(format!("Undefined method '{}' on list", name)),
+    }
+}
+
+fn map_method(
+    entries: &Rc<RefCell<OrderedMap>>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match name {
+        "len" => {
+            let [] = expect_args(name, args)?;
+            Ok(Value::Int(entries.borrow().len() as i64))
+        }
+        "keys" => {
+            let [] = expect_args(name, args)?;
+            Ok(Value::list(entries.borrow().keys().cloned().collect()))
+        }
+        "values" => {
+            let [] = expect_args(name, args)?;
+            Ok(Value::list(entries.borrow().values().cloned().collect()))
+        }
+        "has" => {
+            let [k] = expect_args(name, args)?;
+            Ok(Value::Bool(entries.borrow().contains(&k)?))
+        }
+        "remove" => {
+            let [k] = expect_args(name, args)?;
+            let removed = entries.borrow_mut().remove(&k)?;
+            removed.ok_or_else(|| format!("Key {} not found", k.repr()))
+        }
+        _ => Err(format!("Undefined method '{}' on map", name)),
+    }
+}
+
+// Positions and lengths count characters, not bytes.
+fn string_method(s: &str, name: &str, args: Vec<Value>) -> Result<Value, String> {
+    let string = |s: String| Ok(Value::Str(s));
+    match name {
+        "len" => {
+            let [] = expect_args(name, args)?;
+            Ok(Value::Int(s.chars().count() as i64))
+        }
+        "upper" => {
+            let [] = expect_args(name, args)?;
+            string(s.to_uppercase())
+        }
+        "lower" => {
+            let [] = expect_args(name, args)?;
+            string(s.to_lowercase())
+        }
+        "trim" => {
+            let [] = expect_args(name, args)?;
+            string(s.trim().to_string())
+        }
+        // An empty separator splits into characters.
+        "split" => {
+            let [sep] = expect_args(name, args)?;
+            let parts: Vec<Value> = match as_str(name, &sep)? {
+                "" => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+                sep => s.split(sep).map(|p| Value::Str(p.to_string())).collect(),
+            };
+            Ok(Value::list(parts))
+        }
+        // Joins the elements of a list, converted like `str()`, with this string between them.
+        "join" => {
+            let [items] = expect_args(name, args)?;
+            match &items {
+                Value::List(items) => {
+                    let parts: Vec<String> = items.borrow().iter().map(Value::to_string).collect();
+                    string(parts.join(s))
+                }
+                v => Err(format!("join() expects a list, not {}", v.type_name())),
+            }
+        }
+        "replace" => {
+            let [from, to] = expect_args(name, args)?;
+            string(s.replace(as_str(name, &from)?, as_str(name, &to)?))
+        }
+        "contains" => {
+            let [sub] = expect_args(name, args)?;
+            Ok(Value::Bool(s.contains(as_str(name, &sub)?)))
+        }
+        "startsWith" => {
+            let [prefix] = expect_args(name, args)?;
+            Ok(Value::Bool(s.starts_with(as_str(name, &prefix)?)))
+        }
+        "endsWith" => {
+            let [suffix] = expect_args(name, args)?;
+            Ok(Value::Bool(s.ends_with(as_str(name, &suffix)?)))
+        }
+        // Index of the first occurrence, or -1.
+        "find" => {
+            let [sub] = expect_args(name, args)?;
+            let i = s
+                .find(as_str(name, &sub)?)
+                .map_or(-1, |byte| s[..byte].chars().count() as i64);
+            Ok(Value::Int(i))
+        }
+        // Like slicing a list: negative positions count from the end and are clamped.
+        "substring" => {
+            let chars: Vec<char> = s.chars().collect();
+            let (start, end) = match args.as_slice() {
+                [start] => (clamp("String", start, chars.len())?, chars.len()),
+                [start, end] => (
+                    clamp("String", start, chars.len())?,
+                    clamp("String", end, chars.len())?,
+                ),
+                _ => {
+                    return Err(format!(
+                        "substring() expects 1 or 2 arguments but got {}",
+                        args.len()
+                    ))
+                }
+            };
+            let sub = if start < end {
+                chars[start..end].iter().collect()
+            } else {
+                String::new()
+            };
+            string(sub)
+        }
+        "repeat" => {
+            let [n] = expect_args(name, args)?;
+            match n {
+                Value::Int(n) if n < 0 => Err(format!("repeat() count {} is negative", n)),
+                Value::Int(n) => {
+                    let len = s.len().saturating_mul(n as usize);
+                    check_length("String", len)?;
+                    string(s.repeat(n as usize))
+                }
+                v => Err(format!("repeat() expects an int, not {}", v.type_name())),
+            }
+        }
+        _ => Err(format!("Undefined method '{}' on string", name)),
+    }
+}
+
+fn as_str<'v>(name: &str, v: &'v Value) -> Result<&'v str, String> {
+    match v {
+        Value::Str(s) => Ok(s),
+        _ => Err(format!(
+            "{}() expects a string, not {}",
+            name,
+            v.type_name()
+        )),
+    }
+}
+
+pub fn expect_args<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N], String> {
+    let count = args.len();
+    <[Value; N]>::try_from(args)
+        .map_err(|_| format!("{}() expects {} arguments but got {}", name, N, count))
+}
+
+// Fails if a string or list would grow past MAX_LENGTH.
+pub fn check_length(kind: &str, len: usize) -> Result<(), String> {
+    if len > MAX_LENGTH {
+        return Err(format!(
+            "{} would be longer than the limit of {}",
+            kind, MAX_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+// `kind` names what is indexed in errors, e.g. "List".
+fn as_int(kind: &str, index: &Value) -> Result<i64, String> {
+    match index {
+        Value::Int(i) => Ok(*i),
+        _ => Err(format!(
+            "{} index must be an int, not {}",
+            kind,
+            index.type_name()
+        )),
+    }
+}
+
+// Resolves a possibly negative index, counted from the end, into a position below `len`.
+pub fn index(kind: &str, index: &Value, len: usize) -> Result<usize, String> {
+    let i = as_int(kind, index)?;
+    let position = if i < 0 { i + len as i64 } else { i };
+
+    if position < 0 || position >= len as i64 {
+        return Err(format!("{} index {} out of range", kind, i));
+    }
+    Ok(position as usize)
+}
+
+// Like `index`, but bounds of a slice are clamped to the list instead of failing.
+fn clamp(kind: &str, index: &Value, len: usize) -> Result<usize, String> {
+    let i = as_int(kind, index)?;
+    let position = if i < 0 { i + len as i64 } else { i };
+    Ok(position.max(0).min(len as i64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ints(values: &[i64]) -> Value {
+        Value::list(values.iter().map(|&i| Value::Int(i)).collect())
+    }
+
+    #[test]
+    fn list_methods() {
+        let xs = ints(&[1, 2, 3]);
+        assert_eq!(call(&xs, "len", vec![]), Ok(Value::Int(3)));
+        assert_eq!(call(&xs, "push", vec![Value::Int(4)]), Ok(Value::Nil));
+        assert_eq!(call(&xs, "pop", vec![]), Ok(Value::Int(4)));
+        assert_eq!(
+            call(&xs, "insert", vec![Value::Int(-1), Value::Int(9)]),
+            Ok(Value::Nil)
+        );
+        assert_eq!(xs, ints(&[1, 2, 9, 3]));
+        assert_eq!(call(&xs, "remove", vec![Value::Int(0)]), Ok(Value::Int(1)));
+        assert_eq!(
+            call(&xs, "insert", vec![Value::Int(3), Value::Int(0)]),
+            Ok(Value::Nil)
+        );
+        assert_eq!(xs, ints(&[2, 9, 3, 0]));
+
+        assert_eq!(
+            call(&xs, "slice", vec![Value::Int(1)]),
+            Ok(ints(&[9, 3, 0]))
+        );
+        assert_eq!(
+            call(&xs, "slice", vec![Value::Int(-3), Value::Int(-1)]),
+            Ok(ints(&[9, 3]))
+        );
+        assert_eq!(
+            call(&xs, "slice", vec![Value::Int(3), Value::Int(100)]),
+            Ok(ints(&[0]))
+        );
+        assert_eq!(
+            call(&xs, "slice", vec![Value::Int(3), Value::Int(1)]),
+            Ok(ints(&[]))
+        );
+    }
+
+    #[test]
+    fn list_errors() {
+        let xs = ints(&[]);
+        assert!(call(&xs, "pop", vec![]).is_err());
+        assert!(call(&xs, "remove", vec![Value::Int(0)]).is_err());
+        assert!(call(&xs, "push", vec![]).is_err());
+        assert!(call(&xs, "slice", vec![Value::Number(1.)]).is_err());
+        assert!(call(&xs, "nope", vec![]).is_err());
+        assert!(call(&Value::Nil, "len", vec![]).is_err());
+    }
+
+    #[test]
+    fn map_methods() {
+        let mut entries = OrderedMap::new();
+        entries
+            .insert(Value::Str("a".to_string()), Value::Int(1))
+            .unwrap();
+        entries.insert(Value::Int(2), Value::Nil).unwrap();
+        let m = Value::map(entries);
+
+        assert_eq!(call(&m, "len", vec![]), Ok(Value::Int(2)));
+        assert_eq!(call(&m, "keys", vec![]).unwrap().to_string(), "[\"a\", 2]");
+        assert_eq!(call(&m, "values", vec![]).unwrap().to_string(), "[1, nil]");
+        assert_eq!(
+            call(&m, "has", vec![Value::Number(2.)]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(call(&m, "remove", vec![Value::Int(2)]), Ok(Value::Nil));
+        assert_eq!(call(&m, "has", vec![Value::Int(2)]), Ok(Value::Bool(false)));
+        assert_eq!(
+            call(&m, "remove", vec![Value::Int(2)]),
+            Err("Key 2 not found".to_string())
+        );
+        assert!(call(&m, "has", vec![Value::list(vec![])]).is_err());
+    }
+
+    #[test]
+    fn string_methods() {
+        let s = |s: &str| Value::Str(s.to_string());
+        let text = s("  Grüße, Welt  ");
+        assert_eq!(call(&text, "len", vec![]), Ok(Value::Int(15)));
+        assert_eq!(call(&text, "trim", vec![]), Ok(s("Grüße, Welt")));
+        assert_eq!(call(&s("straße"), "upper", vec![]), Ok(s("STRASSE")));
+        assert_eq!(call(&s("ÄB"), "lower", vec![]), Ok(s("äb")));
+        assert_eq!(
+            call(&s("a,b,,c"), "split", vec![s(",")])
+                .unwrap()
+                .to_string(),
+            "[\"a\", \"b\", \"\", \"c\"]"
+        );
+        assert_eq!(
+            call(&s("hé"), "split", vec![s("")]).unwrap().to_string(),
+            "[\"h\", \"é\"]"
+        );
+        let parts = Value::list(vec![s("a"), Value::Int(1), Value::Nil]);
+        assert_eq!(call(&s("-"), "join", vec![parts]), Ok(s("a-1-nil")));
+        assert_eq!(
+            call(&s("aXbX"), "replace", vec![s("X"), s("yy")]),
+            Ok(s("ayybyy"))
+        );
+        assert_eq!(call(&text, "contains", vec![s("ß")]), Ok(Value::Bool(true)));
+        assert_eq!(
+            call(&s("abc"), "startsWith", vec![s("ab")]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            call(&s("abc"), "endsWith", vec![s("ab")]),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(call(&s("äöü"), "find", vec![s("ü")]), Ok(Value::Int(2)));
+        assert_eq!(call(&s("abc"), "find", vec![s("x")]), Ok(Value::Int(-1)));
+        assert_eq!(
+            call(&s("äöü"), "substring", vec![Value::Int(1)]),
+            Ok(s("öü"))
+        );
+        assert_eq!(
+            call(&s("äöü"), "substring", vec![Value::Int(-2), Value::Int(-1)]),
+            Ok(s("ö"))
+        );
+        assert_eq!(
+            call(&s("ab"), "repeat", vec![Value::Int(3)]),
+            Ok(s("ababab"))
+        );
+
+        assert!(call(&s("a"), "repeat", vec![Value::Int(-1)]).is_err());
+        assert!(call(&s("ab"), "repeat", vec![Value::Int(i64::MAX)]).is_err());
+        assert_eq!(
+            call(&s("a"), "repeat", vec![Value::Int(1 << 40)]),
+            Err("String would be longer than the limit of 16777216".to_string())
+        );
+        assert_eq!(
+            call(&s(""), "repeat", vec![Value::Int(i64::MAX)]),
+            Ok(s(""))
+        );
+        assert!(call(&s("a"), "split", vec![Value::Int(1)]).is_err());
+        assert!(call(&s("a"), "join", vec![s("b")]).is_err());
+        assert!(call(&s("a"), "nope", vec![]).is_err());
+    }
+
+    #[test]
+    fn indexes() {
+        assert_eq!(index("List", &Value::Int(-1), 3), Ok(2));
+        assert_eq!(index("List", &Value::Int(2), 3), Ok(2));
+        assert!(index("List", &Value::Int(3), 3).is_err());
+        assert!(index("List", &Value::Int(-4), 3).is_err());
+        assert!(index("List", &Value::Str("0".to_string()), 3).is_err());
+        assert_eq!(
+            index("String", &Value::Int(10), 3),
+            Err("String index 10 out of range".to_string())
+        );
+    }
+}
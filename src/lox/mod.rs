@@ -1,39 +1,139 @@
 mod ast;
 mod astprinter;
+pub mod dap;
 mod errors;
+mod formatter;
+pub mod fuzzing;
+pub mod lsp;
 mod parser;
+mod protocol;
+mod resolver;
 mod scanner;
+mod stdlib;
 mod tokens;
 mod interpreter;
+mod map;
+mod methods;
+mod module;
 mod value;
+#[cfg(test)]
+mod properties;
+#[cfg(test)]
+mod snapshots;
 
 use scanner::Scanner;
 use parser::Parser;
 use interpreter::Interpreter;
+use errors::CompileError;
+use std::fmt;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::thread;
 
-pub fn run(source: String) -> Result<(), String> {
-    let mut s = Scanner::new(&source);
-    match s.scan_tokens() {
-        Ok(tokens) => {
+pub use formatter::DEFAULT_WIDTH;
+pub use stdlib::Capabilities;
 
-            let mut p = Parser::new(&tokens);
-            let stmts = p.parse();
+// Why running a script failed, which decides the exit code of the CLI.
+#[derive(Debug)]
+pub enum RunError {
+    Io(String),
+    Compile(String),
+    Runtime(String),
+}
 
-            match stmts {
-                Ok(stmts) => {
-                    let mut interpreter = Interpreter::new();
-                    println!("tokens: {:?}", stmts);
+impl RunError {
+    // The codes the reference implementation uses, from sysexits.h.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunError::Io(_) => 66,
+            RunError::Compile(_) => 65,
+            RunError::Runtime(_) => 70,
+        }
+    }
+}
 
-                    if let Err(e) = interpreter.exec(stmts) {
-                        return Err(e.to_string());
-                    }
-                }
-                Err(e) => return Err(e.to_string()),        
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Io(msg) | RunError::Compile(msg) | RunError::Runtime(msg) => {
+                write!(f, "{}", msg)
             }
-    
         }
-        Err(e) => return Err(e.to_string()),
     }
+}
+
+pub fn run(source: String, capabilities: Capabilities) -> Result<(), RunError> {
+    execute(&mut Interpreter::with_capabilities(capabilities), &source)
+}
+
+// Runs a script file. Its imports are resolved relative to the file first,
+// then in each directory of `search_path`.
+pub fn run_file(
+    path: &Path,
+    search_path: Vec<PathBuf>,
+    capabilities: Capabilities,
+) -> Result<(), RunError> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| RunError::Io(format!("{}: {}", path.display(), e)))?;
+
+    let mut interpreter = Interpreter::with_capabilities(capabilities);
+    interpreter.set_search_path(search_path);
+    interpreter.set_file(path);
+    execute(&mut interpreter, &source)
+}
+
+// Runs source a piece at a time, like the lines typed into the REPL. Each piece
+// sees the variables and imports of the ones before it.
+pub struct Session {
+    interpreter: Interpreter,
+}
+
+impl Session {
+    pub fn new(capabilities: Capabilities) -> Self {
+        Session {
+            interpreter: Interpreter::with_capabilities(capabilities),
+        }
+    }
+
+    pub fn run(&mut self, source: &str) -> Result<(), RunError> {
+        execute(&mut self.interpreter, source)
+    }
+}
+
+fn execute(interpreter: &mut Interpreter, source: &str) -> Result<(), RunError> {
+    let compile_error = |e: CompileError| RunError::Compile(e.to_string());
+    let mut s = Scanner::new(source);
+    let tokens = s.scan_tokens().map_err(compile_error)?;
+    let mut p = Parser::new(tokens);
+    let stmts = p.parse().map_err(compile_error)?;
+
+    interpreter.exec(stmts).map_err(|e| {
+        if e.is_runtime() {
+            RunError::Runtime(e.to_string())
+        } else {
+            compile_error(e)
+        }
+    })
+}
+
+pub fn format(source: &str, width: usize) -> Result<String, String> {
+    formatter::format_source(source, width).map_err(|e| e.to_string())
+}
+
+// Parsing, running and formatting recurse once per nesting level, which takes
+// up to about 50KB of stack in an unoptimized build.
+const STACK_PER_LEVEL: usize = 64 << 10;
 
-    Ok(())
+// Runs `f` on a thread with enough stack for the deepest nesting the parser
+// allows, however little the calling thread has left.
+pub fn with_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    let size = (parser::MAX_DEPTH + 16) * STACK_PER_LEVEL;
+    thread::scope(|scope| {
+        let worker = thread::Builder::new()
+            .stack_size(size)
+            .spawn_scoped(scope, f)
+            .expect("failed to spawn a thread");
+        worker.join().unwrap_or_else(|e| panic::resume_unwind(e))
+    })
 }
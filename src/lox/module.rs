@@ -0,0 +1,26 @@
+use super::value::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// A file evaluated by `import`, with the values of the variables it exported.
+#[derive(Debug)]
+pub struct Module {
+    pub path: PathBuf,
+    pub exports: HashMap<String, Value>,
+}
+
+// Finds the file an import names, first relative to the directory of the
+// importing file and then in each directory of the search path.
+pub fn resolve(name: &str, dir: &Path, search_path: &[PathBuf]) -> Result<PathBuf, String> {
+    let dirs = std::iter::once(dir).chain(search_path.iter().map(PathBuf::as_path));
+    for dir in dirs {
+        let file = dir.join(name);
+        if file.is_file() {
+            return file
+                .canonicalize()
+                .map_err(|e| format!("{}: {}", file.display(), e));
+        }
+    }
+
+    Err(format!("Can't find module '{}'", name))
+}
@@ -1,20 +1,106 @@
 use super::ast::*;
 use super::errors;
 use super::tokens::*;
+use errors::CompileError;
 use errors::CompileErrorType::*;
-use errors::{CompileError, CompileErrorType};
 
 use TokenType::*;
 
+// How deeply statements and expressions may nest. Parsing, evaluating and
+// formatting recurse once per level, so this keeps them from overflowing the stack.
+pub const MAX_DEPTH: usize = 100;
+
 pub struct Parser<'a> {
     pub tokens: &'a Vec<Token<'a>>,
     pub statements: Vec<Stmt<'a>>,
     pub current: usize,
+    // Number of loops around the statement being parsed, `break` and `continue` need one.
+    loop_depth: usize,
+    depth: usize,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt<'a> {
     Expression(Box<Expr<'a>>),
+    Var(Token<'a>, Option<Box<Expr<'a>>>),
+    // Opening `{`, statements and closing `}`.
+    Block(Token<'a>, Vec<Stmt<'a>>, Token<'a>),
+    // Keyword, condition, then and optional else branch.
+    If(
+        Token<'a>,
+        Box<Expr<'a>>,
+        Box<Stmt<'a>>,
+        Option<Box<Stmt<'a>>>,
+    ),
+    // Keyword, condition and body.
+    While(Token<'a>, Box<Expr<'a>>, Box<Stmt<'a>>),
+    // Keyword, initializer, condition, increment and body of `for (init; cond; incr)`.
+    For(
+        Token<'a>,
+        Option<Box<Stmt<'a>>>,
+        Option<Box<Expr<'a>>>,
+        Option<Box<Expr<'a>>>,
+        Box<Stmt<'a>>,
+    ),
+    // Keyword, loop variable, iterated value and body of `for (var x in xs)`.
+    ForIn(Token<'a>, Token<'a>, Box<Expr<'a>>, Box<Stmt<'a>>),
+    Break(Token<'a>),
+    Continue(Token<'a>),
+    Throw(Token<'a>, Box<Expr<'a>>),
+    // Keyword, path string, `as` name and the `(name, local name)` pairs of
+    // `import { name as local } from "path"`. A plain `import "path"` has neither.
+    Import(
+        Token<'a>,
+        Token<'a>,
+        Option<Token<'a>>,
+        Vec<(Token<'a>, Token<'a>)>,
+    ),
+    // Keyword and the exported variable declaration.
+    Export(Token<'a>, Box<Stmt<'a>>),
+    // Keyword, try block, catch variable and block, finally block. At least one of
+    // the last two is present.
+    Try(
+        Token<'a>,
+        Box<Stmt<'a>>,
+        Option<(Token<'a>, Box<Stmt<'a>>)>,
+        Option<Box<Stmt<'a>>>,
+    ),
+}
+
+impl<'a> Stmt<'a> {
+    pub fn line(&self) -> usize {
+        match self {
+            Stmt::Expression(e) => e.line(),
+            Stmt::Var(name, _) => name.line,
+            Stmt::Block(keyword, _, _)
+            | Stmt::If(keyword, _, _, _)
+            | Stmt::While(keyword, _, _)
+            | Stmt::For(keyword, _, _, _, _)
+            | Stmt::ForIn(keyword, _, _, _)
+            | Stmt::Break(keyword)
+            | Stmt::Continue(keyword)
+            | Stmt::Throw(keyword, _)
+            | Stmt::Import(keyword, _, _, _)
+            | Stmt::Export(keyword, _)
+            | Stmt::Try(keyword, _, _, _) => keyword.line,
+        }
+    }
+}
+
+// An `else if` chain is dropped a branch at a time, it may be longer than the stack
+// allows recursing.
+impl Drop for Stmt<'_> {
+    fn drop(&mut self) {
+        if let Stmt::If(_, _, _, otherwise) = self {
+            let mut next = otherwise.take();
+            while let Some(mut stmt) = next {
+                next = match &mut *stmt {
+                    Stmt::If(_, _, _, otherwise) => otherwise.take(),
+                    _ => None,
+                };
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -24,65 +110,442 @@ impl<'a> Parser<'a> {
             tokens,
             statements: Vec::new(),
             current: 0,
+            loop_depth: 0,
+            depth: 0,
         }
     }
 
-    pub fn parse(self: &mut Self) -> Result<&Vec<Stmt<'a>>, CompileError> {
+    pub fn parse(&mut self) -> Result<&Vec<Stmt<'a>>, CompileError> {
         while !self.is_at_end() {
-            let e = self.expression()?;
-            self.statements.push(Stmt::Expression(Box::new(e)));
+            let s = self.top_level()?;
+            self.statements.push(s);
         }
 
         Ok(&self.statements)
     }
 
-    fn expression(&mut self) -> Result<Expr<'a>, CompileError> {
-        self.equality()
+    // Imports and exports are only allowed outside of any block.
+    fn top_level(&mut self) -> Result<Stmt<'a>, CompileError> {
+        let stmt = if let Some(t) = self.match_tokens(&[Import]) {
+            self.import(t)?
+        } else if let Some(t) = self.match_tokens(&[Export]) {
+            self.consume(&Var, "Expect 'var' after 'export'.")?;
+            Stmt::Export(t, Box::new(self.var_declaration()?))
+        } else {
+            return self.declaration();
+        };
+
+        self.match_tokens(&[Semicolon]);
+        Ok(stmt)
     }
 
-    fn equality(&mut self) -> Result<Expr<'a>, CompileError> {
-        let mut exp = self.comparison()?;
+    // `import "path" as name`, `import { a, b as c } from "path"` or `import "path"`.
+    fn import(&mut self, keyword: Token<'a>) -> Result<Stmt<'a>, CompileError> {
+        if self.match_tokens(&[LeftBrace]).is_none() {
+            let path = self
+                .consume(&Str, "Expect module path after 'import'.")?
+                .clone();
+            let alias = if self.match_word("as") {
+                Some(
+                    self.consume(&Identifier, "Expect module name after 'as'.")?
+                        .clone(),
+                )
+            } else {
+                None
+            };
+            return Ok(Stmt::Import(keyword, path, alias, Vec::new()));
+        }
 
-        while let Some(op) = self.match_tokens(&[BangEqual, EqualEqual]) {
-            exp = Expr::Binary(Box::new(exp), op, Box::new(self.comparison()?));
+        let mut names = Vec::new();
+        while !self.check(&RightBrace) {
+            let name = self.consume(&Identifier, "Expect name to import.")?.clone();
+            let local = if self.match_word("as") {
+                self.consume(&Identifier, "Expect name after 'as'.")?
+                    .clone()
+            } else {
+                name.clone()
+            };
+            names.push((name, local));
+            if self.match_tokens(&[Comma]).is_none() {
+                break;
+            }
+        }
+        self.consume(&RightBrace, "Expect '}' after imported names.")?;
+        if !self.match_word("from") {
+            return self.error_at(self.peek(), "Expect 'from' after imported names.");
         }
+        let path = self
+            .consume(&Str, "Expect module path after 'from'.")?
+            .clone();
 
-        return Ok(exp);
+        Ok(Stmt::Import(keyword, path, None, names))
     }
 
-    fn comparison(&mut self) -> Result<Expr<'a>, CompileError> {
-        let mut exp = self.term()?;
-        while let Some(op) = self.match_tokens(&[Greater, GreaterEqual, Less, LessEqual]) {
-            exp = Expr::Binary(Box::new(exp), op, Box::new(self.term()?));
+    // Statements may be terminated with an optional `;`.
+    fn declaration(&mut self) -> Result<Stmt<'a>, CompileError> {
+        self.nested(Self::statement)
+    }
+
+    fn statement(&mut self) -> Result<Stmt<'a>, CompileError> {
+        let stmt = if let Some(t) = self.match_tokens(&[Import, Export]) {
+            let msg = format!("'{}' is only allowed at the top level.", t.lexeme);
+            return self.error_at(&t, &msg);
+        } else if self.match_tokens(&[Var]).is_some() {
+            self.var_declaration()?
+        } else if self.check(&LeftBrace) && !self.is_map_literal() {
+            let open = self.advance().clone();
+            return self.block(open);
+        } else if let Some(t) = self.match_tokens(&[If]) {
+            return self.if_statement(t);
+        } else if let Some(t) = self.match_tokens(&[While]) {
+            return self.while_statement(t);
+        } else if let Some(t) = self.match_tokens(&[For]) {
+            return self.for_statement(t);
+        } else if let Some(t) = self.match_tokens(&[Try]) {
+            return self.try_statement(t);
+        } else if let Some(t) = self.match_tokens(&[Throw]) {
+            Stmt::Throw(t, Box::new(self.expression()?))
+        } else if let Some(t) = self.match_tokens(&[Break, Continue]) {
+            if self.loop_depth == 0 {
+                let msg = format!("Can't use '{}' outside of a loop.", t.lexeme);
+                return self.error_at(&t, &msg);
+            }
+            if t.ttype == Break {
+                Stmt::Break(t)
+            } else {
+                Stmt::Continue(t)
+            }
+        } else {
+            Stmt::Expression(Box::new(self.expression()?))
+        };
+
+        self.match_tokens(&[Semicolon]);
+        Ok(stmt)
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt<'a>, CompileError> {
+        let name = self.consume(&Identifier, "Expect variable name.")?.clone();
+        self.var_initializer(name)
+    }
+
+    fn var_initializer(&mut self, name: Token<'a>) -> Result<Stmt<'a>, CompileError> {
+        let init = if self.match_tokens(&[Equal]).is_some() {
+            Some(Box::new(self.expression()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::Var(name, init))
+    }
+
+    fn block(&mut self, open: Token<'a>) -> Result<Stmt<'a>, CompileError> {
+        let mut stmts = Vec::new();
+        while !self.check(&RightBrace) && !self.is_at_end() {
+            stmts.push(self.declaration()?);
         }
 
-        return Ok(exp);
+        let close = self
+            .consume(&RightBrace, "Expect '}' after block.")?
+            .clone();
+        Ok(Stmt::Block(open, stmts, close))
     }
 
-    fn term(&mut self) -> Result<Expr<'a>, CompileError> {
-        let mut exp = self.factor()?;
-        while let Some(op) = self.match_tokens(&[Minus, Plus]) {
-            exp = Expr::Binary(Box::new(exp), op, Box::new(self.factor()?));
+    // An `else if` chain is read in a loop, so it doesn't count as nesting however
+    // long it gets. It still becomes an `If` in the else branch of the one before.
+    fn if_statement(&mut self, keyword: Token<'a>) -> Result<Stmt<'a>, CompileError> {
+        let mut branches = Vec::new();
+        let mut keyword = keyword;
+        let mut otherwise = loop {
+            self.consume(&LeftParen, "Expect '(' after 'if'.")?;
+            let cond = self.expression()?;
+            self.consume(&RightParen, "Expect ')' after if condition.")?;
+            branches.push((keyword, cond, self.declaration()?));
+
+            if self.match_tokens(&[Else]).is_none() {
+                break None;
+            }
+            match self.match_tokens(&[If]) {
+                Some(t) => keyword = t,
+                None => break Some(Box::new(self.declaration()?)),
+            }
+        };
+
+        while let Some((keyword, cond, then)) = branches.pop() {
+            let branch = Stmt::If(keyword, Box::new(cond), Box::new(then), otherwise);
+            otherwise = Some(Box::new(branch));
         }
+        Ok(*otherwise.expect("an if statement has at least one branch"))
+    }
 
-        return Ok(exp);
+    fn while_statement(&mut self, keyword: Token<'a>) -> Result<Stmt<'a>, CompileError> {
+        self.consume(&LeftParen, "Expect '(' after 'while'.")?;
+        let cond = self.expression()?;
+        self.consume(&RightParen, "Expect ')' after condition.")?;
+
+        let body = self.loop_body()?;
+        Ok(Stmt::While(keyword, Box::new(cond), Box::new(body)))
     }
 
-    fn factor(&mut self) -> Result<Expr<'a>, CompileError> {
-        let mut exp = self.unary()?;
-        while let Some(op) = self.match_tokens(&[Slash, Star]) {
-            exp = Expr::Binary(Box::new(exp), op, Box::new(self.unary()?));
+    // Either `for (var x in xs)` or the C style `for (init; cond; incr)`.
+    fn for_statement(&mut self, keyword: Token<'a>) -> Result<Stmt<'a>, CompileError> {
+        self.consume(&LeftParen, "Expect '(' after 'for'.")?;
+
+        let init = if self.match_tokens(&[Semicolon]).is_some() {
+            None
+        } else if self.match_tokens(&[Var]).is_some() {
+            let name = self.consume(&Identifier, "Expect variable name.")?.clone();
+            if self.match_tokens(&[In]).is_some() {
+                let iterable = self.expression()?;
+                self.consume(&RightParen, "Expect ')' after for-in clause.")?;
+                let body = self.loop_body()?;
+                return Ok(Stmt::ForIn(
+                    keyword,
+                    name,
+                    Box::new(iterable),
+                    Box::new(body),
+                ));
+            }
+
+            let init = self.var_initializer(name)?;
+            self.consume(&Semicolon, "Expect ';' after loop initializer.")?;
+            Some(Box::new(init))
+        } else {
+            let init = self.expression()?;
+            self.consume(&Semicolon, "Expect ';' after loop initializer.")?;
+            Some(Box::new(Stmt::Expression(Box::new(init))))
+        };
+
+        let cond = if self.check(&Semicolon) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+        self.consume(&Semicolon, "Expect ';' after loop condition.")?;
+
+        let incr = if self.check(&RightParen) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+        self.consume(&RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.loop_body()?;
+        Ok(Stmt::For(keyword, init, cond, incr, Box::new(body)))
+    }
+
+    fn try_statement(&mut self, keyword: Token<'a>) -> Result<Stmt<'a>, CompileError> {
+        let body = self.block_statement("Expect '{' after 'try'.")?;
+
+        let catch = if self.match_tokens(&[Catch]).is_some() {
+            self.consume(&LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self
+                .consume(&Identifier, "Expect error variable name.")?
+                .clone();
+            self.consume(&RightParen, "Expect ')' after error variable.")?;
+            Some((
+                name,
+                Box::new(self.block_statement("Expect '{' after catch clause.")?),
+            ))
+        } else {
+            None
+        };
+
+        let finally = if self.match_tokens(&[Finally]).is_some() {
+            Some(Box::new(
+                self.block_statement("Expect '{' after 'finally'.")?,
+            ))
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return self.error_at(self.peek(), "Expect 'catch' or 'finally' after try block.");
+        }
+        Ok(Stmt::Try(keyword, Box::new(body), catch, finally))
+    }
+
+    fn block_statement(&mut self, error_msg: &str) -> Result<Stmt<'a>, CompileError> {
+        let open = self.consume(&LeftBrace, error_msg)?.clone();
+        self.block(open)
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt<'a>, CompileError> {
+        self.loop_depth += 1;
+        let body = self.declaration();
+        self.loop_depth -= 1;
+        body
+    }
+
+    // A `{` at the start of a statement opens a map literal when its first entry is
+    // followed by `:`, and a block otherwise. `{}` is an empty block, so an empty map
+    // statement has to be written `({})`.
+    fn is_map_literal(&mut self) -> bool {
+        let start = self.current;
+        self.advance();
+        let is_map = self.assignment().is_ok() && self.check(&Colon);
+        self.current = start;
+        is_map
+    }
+
+    fn expression(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.comma()
+    }
+
+    // The comma operator evaluates both sides and yields the right one.
+    fn comma(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[Comma], Self::assignment)
+    }
+
+    fn assignment(&mut self) -> Result<Expr<'a>, CompileError> {
+        let exp = self.conditional()?;
+
+        if let Some(op) = self.match_tokens(&[Equal, PlusEqual, MinusEqual, StarEqual, SlashEqual])
+        {
+            let value = self.nested(Self::assignment)?;
+            return match exp {
+                Expr::Identifier(name) => Ok(Expr::Assign(name, op, Box::new(value))),
+                Expr::Index(obj, _, index) => Ok(Expr::SetIndex(obj, index, op, Box::new(value))),
+                _ => self.error_at(&op, "Invalid assignment target."),
+            };
+        }
+
+        Ok(exp)
+    }
+
+    fn conditional(&mut self) -> Result<Expr<'a>, CompileError> {
+        let cond = self.equality()?;
+
+        if self.match_tokens(&[Question]).is_some() {
+            let then = self.nested(Self::expression)?;
+            self.consume(&Colon, "Expect ':' in conditional expression.")?;
+            let otherwise = self.nested(Self::conditional)?;
+            return Ok(Expr::Conditional(
+                Box::new(cond),
+                Box::new(then),
+                Box::new(otherwise),
+            ));
         }
 
-        return Ok(exp);
+        Ok(cond)
+    }
+
+    // A left-associative chain of operands. Each operator nests the tree one level
+    // deeper, so long chains count against the nesting limit like brackets do.
+    fn binary(
+        &mut self,
+        ops: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expr<'a>, CompileError>,
+    ) -> Result<Expr<'a>, CompileError> {
+        self.chain(|p| {
+            let mut exp = operand(p)?;
+            while let Some(op) = p.match_tokens(ops) {
+                p.deeper()?;
+                exp = Expr::Binary(Box::new(exp), op, Box::new(operand(p)?));
+            }
+
+            Ok(exp)
+        })
+    }
+
+    fn equality(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[BangEqual, EqualEqual], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[Greater, GreaterEqual, Less, LessEqual], Self::bit_or)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[Pipe], Self::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[Caret], Self::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[Ampersand], Self::shift)
     }
 
+    fn shift(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[LessLess, GreaterGreater], Self::term)
+    }
+
+    fn term(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[Minus, Plus], Self::factor)
+    }
+
+    fn factor(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.binary(&[Slash, Star, Percent], Self::unary)
+    }
+
+    // Every bracket passes through here, so this also limits how deeply they nest.
     fn unary(&mut self) -> Result<Expr<'a>, CompileError> {
-        if let Some(op) = self.match_tokens(&[Bang, Minus]) {
-            return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+        self.nested(|p| {
+            if let Some(op) = p.match_tokens(&[Bang, Minus, Tilde]) {
+                return Ok(Expr::Unary(op, Box::new(p.unary()?)));
+            }
+
+            p.power()
+        })
+    }
+
+    // `**` binds tighter than unary operators on its left and is right-associative,
+    // so `-2 ** 2` is `-(2 ** 2)` and `2 ** -1` is allowed.
+    fn power(&mut self) -> Result<Expr<'a>, CompileError> {
+        let exp = self.call()?;
+        if let Some(op) = self.match_tokens(&[StarStar]) {
+            return Ok(Expr::Binary(Box::new(exp), op, Box::new(self.unary()?)));
+        }
+
+        Ok(exp)
+    }
+
+    // Calls, properties and indexes nest the tree like binary operators do.
+    fn call(&mut self) -> Result<Expr<'a>, CompileError> {
+        self.chain(|p| {
+            let mut exp = p.primary()?;
+
+            loop {
+                if p.match_tokens(&[LeftParen]).is_some() {
+                    p.deeper()?;
+                    let args = p.arguments(&RightParen)?;
+                    let paren = p
+                        .consume(&RightParen, "Expect ')' after arguments.")?
+                        .clone();
+                    exp = Expr::Call(Box::new(exp), paren, args);
+                } else if p.match_tokens(&[Dot]).is_some() {
+                    p.deeper()?;
+                    let name = p
+                        .consume(&Identifier, "Expect property name after '.'.")?
+                        .clone();
+                    exp = Expr::Get(Box::new(exp), name);
+                } else if p.match_tokens(&[LeftBracket]).is_some() {
+                    p.deeper()?;
+                    let index = p.expression()?;
+                    let bracket = p.consume(&RightBracket, "Expect ']' after index.")?.clone();
+                    exp = Expr::Index(Box::new(exp), bracket, Box::new(index));
+                } else {
+                    break;
+                }
+            }
+
+            Ok(exp)
+        })
+    }
+
+    // Comma separated expressions up to `end`, which is left for the caller to consume.
+    // A trailing comma is allowed.
+    fn arguments(&mut self, end: &TokenType) -> Result<Vec<Expr<'a>>, CompileError> {
+        let mut args = Vec::new();
+        while !self.check(end) {
+            args.push(self.assignment()?);
+            if self.match_tokens(&[Comma]).is_none() {
+                break;
+            }
         }
 
-        self.primary()
+        Ok(args)
     }
 
     fn primary(&mut self) -> Result<Expr<'a>, CompileError> {
@@ -90,13 +553,67 @@ impl<'a> Parser<'a> {
             return Ok(Expr::Literal(t));
         }
 
-        if let Some(_) = self.match_tokens(&[LeftParen]) {
+        if let Some(t) = self.match_tokens(&[Identifier]) {
+            return Ok(Expr::Identifier(t));
+        }
+
+        if let Some(t) = self.match_tokens(&[Interpolation]) {
+            return self.interpolation(t);
+        }
+
+        if self.match_tokens(&[LeftBracket]).is_some() {
+            let items = self.arguments(&RightBracket)?;
+            let bracket = self
+                .consume(&RightBracket, "Expect ']' after list elements.")?
+                .clone();
+            return Ok(Expr::List(bracket, items));
+        }
+
+        if self.match_tokens(&[LeftBrace]).is_some() {
+            return self.map();
+        }
+
+        if self.match_tokens(&[LeftParen]).is_some() {
             let e = self.expression()?;
             self.consume(&RightParen, "Expect ')' after expression.")?;
             return Ok(Expr::Grouping(Box::new(e)));
         }
 
-        self.build_error(InvalidSyntax, "Uexpected expression")
+        self.error_at(self.peek(), "Expect expression.")
+    }
+
+    // Entries of a map literal after its `{`, with an optional trailing comma.
+    fn map(&mut self) -> Result<Expr<'a>, CompileError> {
+        let mut entries = Vec::new();
+        while !self.check(&RightBrace) {
+            let key = self.assignment()?;
+            self.consume(&Colon, "Expect ':' after map key.")?;
+            entries.push((key, self.assignment()?));
+            if self.match_tokens(&[Comma]).is_none() {
+                break;
+            }
+        }
+
+        let brace = self
+            .consume(&RightBrace, "Expect '}' after map entries.")?
+            .clone();
+        Ok(Expr::Map(brace, entries))
+    }
+
+    fn interpolation(&mut self, head: Token<'a>) -> Result<Expr<'a>, CompileError> {
+        let mut parts = vec![Expr::Literal(head)];
+
+        loop {
+            parts.push(self.expression()?);
+
+            if let Some(t) = self.match_tokens(&[Interpolation]) {
+                parts.push(Expr::Literal(t));
+            } else {
+                let t = self.consume(&Str, "Expect end of string interpolation.")?;
+                parts.push(Expr::Literal(t.clone()));
+                return Ok(Expr::Interpolation(parts));
+            }
+        }
     }
 
     fn consume(&mut self, ttype: &TokenType, error_msg: &str) -> Result<&Token<'a>, CompileError> {
@@ -104,31 +621,59 @@ impl<'a> Parser<'a> {
             return Ok(self.advance());
         }
 
-        self.build_error(InvalidSyntax, error_msg)
+        self.error_at(self.peek(), error_msg)
     }
 
-    fn build_error<T>(
-        self: &Self,
-        error_type: CompileErrorType,
-        msg: &str,
-    ) -> Result<T, CompileError> {
+    // A syntax error found at `token`, reported like `Error at ';'`.
+    fn error_at<T>(&self, token: &Token, msg: &str) -> Result<T, CompileError> {
+        let at = match token.ttype {
+            Eof => "end".to_string(),
+            _ => format!("'{}'", token.lexeme),
+        };
         Err(CompileError {
-            err: error_type,
-            line: self.previous().line,
-            text: if msg.is_empty() {
-                None
-            } else {
-                Some(msg.to_string())
-            },
+            err: InvalidSyntax,
+            line: token.line,
+            text: Some(msg.to_string()),
+            at: Some(at),
         })
     }
 
+    // Parses one level deeper.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, CompileError>,
+    ) -> Result<T, CompileError> {
+        self.chain(|p| {
+            p.deeper()?;
+            parse(p)
+        })
+    }
+
+    // Runs `parse`, which may go deeper any number of times, and comes back up after.
+    fn chain<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, CompileError>,
+    ) -> Result<T, CompileError> {
+        let depth = self.depth;
+        let result = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    fn deeper(&mut self) -> Result<(), CompileError> {
+        if self.depth == MAX_DEPTH {
+            return self.error_at(self.peek(), "Too much nesting.");
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
     fn peek(&self) -> &Token<'a> {
         &self.tokens[self.current]
     }
 
     fn previous(&self) -> &Token<'a> {
-        &self.tokens[self.current - 1]
+        &self.tokens[self.current.saturating_sub(1)]
     }
 
     fn advance(&mut self) -> &Token<'a> {
@@ -148,13 +693,22 @@ impl<'a> Parser<'a> {
 
     fn match_tokens(&mut self, ttypes: &[TokenType]) -> Option<Token<'a>> {
         for ttype in ttypes {
-            if self.check(&ttype) {
+            if self.check(ttype) {
                 return Some(self.advance().clone());
             }
         }
         None
     }
 
+    // Matches an identifier used as a keyword in one place only, like `as` in imports.
+    fn match_word(&mut self, word: &str) -> bool {
+        if self.check(&Identifier) && self.peek().lexeme == word {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
     fn is_at_end(&self) -> bool {
         self.peek().ttype == Eof
     }
@@ -170,7 +724,7 @@ mod tests {
     fn test_valid_expr(expr: &str) {
         let mut s = Scanner::new(expr);
         let tokens = s.scan_tokens().unwrap();
-        let mut p = Parser::new(&tokens);
+        let mut p = Parser::new(tokens);
         println!("tokens: {:?}", tokens);
         let stmts = p.parse();
         let s = &stmts.unwrap()[0];
@@ -188,5 +742,198 @@ mod tests {
         test_valid_expr("2123-23232/2");
         test_valid_expr("(-1)+(!5)");
         test_valid_expr("false+true");
+        test_valid_expr("\"a\\n\"+\"\"");
+        test_valid_expr("\"a${1+2}b${\"c${3}\"}\"");
+        test_valid_expr("a=b+=c*d");
+        test_valid_expr("a?b:c?d:e");
+        test_valid_expr("a,b=1,c");
+        test_valid_expr("[1,[a],[]][0]=xs.len()+f(1,2)(3)");
+        test_valid_expr("{1:2,a?b:c:{}}[1]=-1");
+    }
+
+    fn parse_tree(source: &str) -> String {
+        let mut s = Scanner::new(source);
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        match &p.parse().unwrap()[0] {
+            Stmt::Expression(e) => tree(e),
+            _ => panic!("wrong type"),
+        }
+    }
+
+    // Fully parenthesized form of an expression to check precedence and associativity.
+    fn tree(e: &Expr) -> String {
+        match e {
+            Expr::Binary(l, op, r) => format!("({} {} {})", tree(l), op.lexeme, tree(r)),
+            Expr::Unary(op, e) => format!("({}{})", op.lexeme, tree(e)),
+            Expr::Grouping(e) => tree(e),
+            Expr::Conditional(c, a, b) => format!("({} ? {} : {})", tree(c), tree(a), tree(b)),
+            Expr::Assign(name, op, e) => format!("({} {} {})", name.lexeme, op.lexeme, tree(e)),
+            _ => astprinter::dump_ast(e),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parse_tree("-2**2"), "(-(2 ** 2))");
+        assert_eq!(parse_tree("2**3**2"), "(2 ** (3 ** 2))");
+        assert_eq!(parse_tree("2**-1"), "(2 ** (-1))");
+        assert_eq!(
+            parse_tree("1|2^3&4<<5+6"),
+            "(1 | (2 ^ (3 & (4 << (5 + 6)))))"
+        );
+        assert_eq!(parse_tree("x&1==0"), "((x & 1) == 0)");
+        assert_eq!(parse_tree("~1%2"), "((~1) % 2)");
+        assert_eq!(parse_tree("a?b:c?d:e"), "(a ? b : (c ? d : e))");
+        assert_eq!(parse_tree("a?b,c:d"), "(a ? (b , c) : d)");
+        assert_eq!(parse_tree("x=a==b?1:2"), "(x = ((a == b) ? 1 : 2))");
+        assert_eq!(parse_tree("a,b,c"), "((a , b) , c)");
+        assert_eq!(parse_tree("a=1,b=2"), "((a = 1) , (b = 2))");
+        assert_eq!(parse_tree("-xs[0]**2"), "(-(xs[0] ** 2))");
+    }
+
+    #[test]
+    fn statements() {
+        let mut s = Scanner::new("var a = 1; var b\na += 2;");
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        let stmts = p.parse().unwrap();
+        assert_eq!(stmts.len(), 3);
+        assert!(matches!(&stmts[0], Stmt::Var(name, Some(_)) if name.lexeme == "a"));
+        assert!(matches!(&stmts[1], Stmt::Var(name, None) if name.lexeme == "b"));
+        assert!(matches!(&stmts[2], Stmt::Expression(e) if matches!(**e, Expr::Assign(..))));
+
+        for bad in &["1 + 2 = 3", "f() = 1", "[1, 2", "xs[1", "f(1 2)", "[,]"] {
+            let mut s = Scanner::new(bad);
+            let tokens = s.scan_tokens().unwrap();
+            let mut p = Parser::new(tokens);
+            assert_eq!(p.parse().unwrap_err().err, InvalidSyntax);
+        }
+
+        let mut s = Scanner::new("{ var a = 1; { a } } {} {a ? b : c} {a ? b : c : d,}");
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        let stmts = p.parse().unwrap();
+        assert_eq!(stmts.len(), 4);
+        assert!(matches!(&stmts[0], Stmt::Block(_, inner, _) if inner.len() == 2));
+        assert!(matches!(&stmts[1], Stmt::Block(_, inner, _) if inner.is_empty()));
+        assert!(matches!(&stmts[2], Stmt::Block(_, inner, _) if inner.len() == 1));
+        assert!(matches!(&stmts[3], Stmt::Expression(e) if matches!(**e, Expr::Map(..))));
+
+        let mut s = Scanner::new(
+            "import \"a.lox\" as a; import { b, c as d, } from \"b.lox\"; import \"c.lox\" export var e = 1",
+        );
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        let stmts = p.parse().unwrap();
+        assert!(
+            matches!(&stmts[0], Stmt::Import(_, _, Some(a), names) if a.lexeme == "a" && names.is_empty())
+        );
+        assert!(
+            matches!(&stmts[1], Stmt::Import(_, _, None, names) if names.len() == 2 && names[1].1.lexeme == "d")
+        );
+        assert!(matches!(&stmts[2], Stmt::Import(_, _, None, names) if names.is_empty()));
+        assert!(matches!(&stmts[3], Stmt::Export(..)));
+
+        for bad in &[
+            "{ 1",
+            "{1: 2",
+            "({1})",
+            "({1: 2 3})",
+            "{ import \"a\" }",
+            "if (a) export var b",
+            "import a",
+            "import { a } \"a\"",
+            "export a = 1",
+        ] {
+            let mut s = Scanner::new(bad);
+            let tokens = s.scan_tokens().unwrap();
+            let mut p = Parser::new(tokens);
+            assert_eq!(p.parse().unwrap_err().err, InvalidSyntax);
+        }
+
+        let mut s = Scanner::new(
+            "if (a) b else { c } while (a) { if (b) break; continue } for (;;) {} for (var x in xs) x",
+        );
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        let stmts = p.parse().unwrap();
+        assert!(matches!(&stmts[0], Stmt::If(_, _, _, Some(_))));
+        assert!(matches!(&stmts[1], Stmt::While(..)));
+        assert!(matches!(&stmts[2], Stmt::For(_, None, None, None, _)));
+        assert!(matches!(&stmts[3], Stmt::ForIn(_, x, _, _) if x.lexeme == "x"));
+
+        for bad in &[
+            "break",
+            "if (a) continue",
+            "{ break; }",
+            "for (var x in xs",
+            "for (;)",
+        ] {
+            let mut s = Scanner::new(bad);
+            let tokens = s.scan_tokens().unwrap();
+            let mut p = Parser::new(tokens);
+            assert_eq!(p.parse().unwrap_err().err, InvalidSyntax);
+        }
+
+        let mut s = Scanner::new("[1, 2,]; f(a,)");
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        assert_eq!(p.parse().unwrap().len(), 2);
+    }
+
+    // Nesting up to the limit needs more stack than test threads get.
+    #[test]
+    fn nesting() {
+        super::super::with_stack(nesting_limit);
+
+        // Errors at the very first token still have a line.
+        let mut s = Scanner::new(")");
+        let tokens = s.scan_tokens().unwrap();
+        assert_eq!(Parser::new(tokens).parse().unwrap_err().line, 1);
+    }
+
+    fn nesting_limit() {
+        let parse = |source: &str| {
+            let mut s = Scanner::new(source);
+            let tokens = s.scan_tokens().unwrap();
+            let mut p = Parser::new(tokens);
+            p.parse().map(|stmts| stmts.len())
+        };
+        let nested = |open: &str, close: &str, n| format!("{}1{}", open.repeat(n), close.repeat(n));
+
+        assert_eq!(parse(&nested("(", ")", 98)), Ok(1));
+        assert_eq!(parse(&nested("{", "}", 98)), Ok(1));
+        for (open, close) in &[
+            ("(", ")"),
+            ("[", "]"),
+            ("{", "}"),
+            ("f(", ")"),
+            ("\"${", "}\""),
+            ("-", ""),
+            ("a = ", ""),
+            ("a ? 1 : ", ""),
+            ("2 ** ", ""),
+            ("if (1) ", ""),
+            ("1 + ", ""),
+            ("1, ", ""),
+            ("a ? ", " : 1"),
+            ("", "[0]"),
+            ("", ".y"),
+            ("", "()"),
+        ] {
+            let e = parse(&nested(open, close, 100_000)).unwrap_err();
+            assert_eq!(
+                e.text,
+                Some("Too much nesting.".to_string()),
+                "{}{}",
+                open,
+                close
+            );
+        }
+
+        // An `else if` chain doesn't nest.
+        let chain = format!("if (0) 0{} else 1", " else if (0) 0".repeat(100_000));
+        assert_eq!(parse(&chain), Ok(1));
     }
 }
@@ -0,0 +1,419 @@
+// Generative tests of expression evaluation. Random well-typed expressions are
+// printed with the AST printer, scanned and parsed back, and run on every
+// backend. Each result must match what a reference evaluator working directly
+// on the generated tree computes, errors included.
+//
+// Cases are derived from a fixed seed, so runs are reproducible. Run with
+// LOX_SEED=N to try other expressions.
+
+use super::ast::Expr;
+use super::astprinter;
+use super::formatter;
+use super::interpreter::Interpreter;
+use super::parser::{Parser, Stmt};
+use super::scanner::Scanner;
+use super::stdlib::Capabilities;
+use super::tokens::{Token, TokenType};
+use super::value::Value;
+use super::DEFAULT_WIDTH;
+use std::env;
+use TokenType::*;
+
+const CASES: u64 = 500;
+const MAX_DEPTH: usize = 5;
+// Generated lists always have this many elements, so any index below it is valid.
+const LIST_LEN: usize = 3;
+
+type Backend = fn(&str) -> Result<Value, String>;
+
+// Every way of running a source, all of which must agree with the reference.
+const BACKENDS: &[(&str, Backend)] = &[("interpreter", interpret), ("formatted", formatted)];
+
+fn interpret(source: &str) -> Result<Value, String> {
+    let mut s = Scanner::new(source);
+    let tokens = s.scan_tokens().map_err(|e| e.to_string())?;
+    let mut p = Parser::new(tokens);
+    let stmts = p.parse().map_err(|e| e.to_string())?;
+    match stmts.as_slice() {
+        [Stmt::Expression(e)] => Interpreter::with_capabilities(Capabilities::none())
+            .evaluate(e)
+            .map_err(|e| e.text.clone().unwrap_or_else(|| e.to_string())),
+        _ => Err(format!("Not a single expression: {:?}", source)),
+    }
+}
+
+// The source as reformatted by `lox fmt`, which must not change what it means.
+fn formatted(source: &str) -> Result<Value, String> {
+    let source = formatter::format_source(source, DEFAULT_WIDTH).map_err(|e| e.to_string())?;
+    interpret(&source)
+}
+
+// xorshift64*, plenty for choosing between a handful of options.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as usize % n
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Type {
+    Int,
+    Bool,
+    Str,
+    // A list of ints, LIST_LEN long.
+    List,
+}
+
+const TYPES: &[Type] = &[Type::Int, Type::Bool, Type::Str, Type::List];
+
+const INTS: &[i64] = &[0, 1, 2, 3, 7, 10, 63, 64, 100, 1 << 40, i64::MAX];
+
+// Lexemes of string literals and the strings they stand for.
+const STRS: &[(&str, &str)] = &[
+    ("\"\"", ""),
+    ("\"a\"", "a"),
+    ("\"lox\"", "lox"),
+    ("\"a\\tb\"", "a\tb"),
+    ("\"\u{fc}\"", "\u{fc}"),
+];
+
+const INT_OPS: &[(TokenType, &str)] = &[
+    (Plus, "+"),
+    (Minus, "-"),
+    (Star, "*"),
+    (Slash, "/"),
+    (Percent, "%"),
+    (Ampersand, "&"),
+    (Pipe, "|"),
+    (Caret, "^"),
+    (LessLess, "<<"),
+    (GreaterGreater, ">>"),
+];
+
+const COMPARISONS: &[(TokenType, &str)] = &[
+    (Less, "<"),
+    (LessEqual, "<="),
+    (Greater, ">"),
+    (GreaterEqual, ">="),
+];
+
+fn token(ttype: TokenType, lexeme: &'static str) -> Token<'static> {
+    Token {
+        ttype,
+        lexeme,
+        literal: None,
+        line: 1,
+        trivia: Vec::new(),
+    }
+}
+
+fn int(i: i64) -> Expr<'static> {
+    Expr::Literal(Token {
+        literal: Some(Value::Int(i)),
+        ..token(Number, "")
+    })
+}
+
+fn binary(
+    l: Expr<'static>,
+    (ttype, lexeme): (TokenType, &'static str),
+    r: Expr<'static>,
+) -> Expr<'static> {
+    Expr::Binary(Box::new(l), token(ttype, lexeme), Box::new(r))
+}
+
+// A random expression of type `ty`, at most `depth` operators deep.
+fn expr(rng: &mut Rng, ty: Type, depth: usize) -> Expr<'static> {
+    if depth == 0 || rng.below(4) == 0 {
+        return leaf(rng, ty);
+    }
+    let depth = depth - 1;
+
+    // Any type can be chosen between or come after a comma.
+    match rng.below(6) {
+        0 => {
+            let c = operand(rng, Type::Bool, depth);
+            let a = operand(rng, ty, depth);
+            let b = operand(rng, ty, depth);
+            return Expr::Conditional(Box::new(c), Box::new(a), Box::new(b));
+        }
+        1 => {
+            let first = *rng.pick(TYPES);
+            let l = operand(rng, first, depth);
+            return binary(l, (Comma, ","), operand(rng, ty, depth));
+        }
+        _ => {}
+    }
+
+    match ty {
+        Type::Int => match rng.below(4) {
+            0 => {
+                let op = rng.pick(&[(Minus, "-"), (Tilde, "~")]).clone();
+                Expr::Unary(token(op.0, op.1), Box::new(operand(rng, ty, depth)))
+            }
+            // Small exponents only, negative ones would give a float.
+            1 => {
+                let l = operand(rng, ty, depth);
+                binary(l, (StarStar, "**"), int(rng.below(6) as i64))
+            }
+            2 => {
+                let list = operand(rng, Type::List, depth);
+                let index = int(rng.below(LIST_LEN) as i64);
+                Expr::Index(Box::new(list), token(RightBracket, "]"), Box::new(index))
+            }
+            _ => {
+                let op = rng.pick(INT_OPS).clone();
+                let l = operand(rng, ty, depth);
+                binary(l, op, operand(rng, ty, depth))
+            }
+        },
+        Type::Bool => match rng.below(3) {
+            0 => {
+                let operand_type = *rng.pick(TYPES);
+                let e = operand(rng, operand_type, depth);
+                Expr::Unary(token(Bang, "!"), Box::new(e))
+            }
+            1 => {
+                let op = rng.pick(COMPARISONS).clone();
+                let l = operand(rng, Type::Int, depth);
+                binary(l, op, operand(rng, Type::Int, depth))
+            }
+            // Values of different types can be compared for equality too.
+            _ => {
+                let op = rng.pick(&[(EqualEqual, "=="), (BangEqual, "!=")]).clone();
+                let (l_type, r_type) = (*rng.pick(TYPES), *rng.pick(TYPES));
+                let l = operand(rng, l_type, depth);
+                binary(l, op, operand(rng, r_type, depth))
+            }
+        },
+        Type::Str => {
+            let l = operand(rng, ty, depth);
+            binary(l, (Plus, "+"), operand(rng, ty, depth))
+        }
+        Type::List => list(rng, depth),
+    }
+}
+
+// Anything but a literal is parenthesized, so the printed form parses back
+// into the same tree whatever the precedence of the surrounding operator.
+fn operand(rng: &mut Rng, ty: Type, depth: usize) -> Expr<'static> {
+    match expr(rng, ty, depth) {
+        e @ Expr::Literal(_) => e,
+        e => Expr::Grouping(Box::new(e)),
+    }
+}
+
+fn leaf(rng: &mut Rng, ty: Type) -> Expr<'static> {
+    match ty {
+        Type::Int => int(*rng.pick(INTS)),
+        Type::Bool => {
+            let (ttype, lexeme) = rng.pick(&[(True, "true"), (False, "false")]).clone();
+            Expr::Literal(token(ttype, lexeme))
+        }
+        Type::Str => {
+            let (lexeme, s) = *rng.pick(STRS);
+            Expr::Literal(Token {
+                literal: Some(Value::Str(s.to_string())),
+                ..token(Str, lexeme)
+            })
+        }
+        Type::List => list(rng, 0),
+    }
+}
+
+fn list(rng: &mut Rng, depth: usize) -> Expr<'static> {
+    let items = (0..LIST_LEN)
+        .map(|_| operand(rng, Type::Int, depth))
+        .collect();
+    Expr::List(token(RightBracket, "]"), items)
+}
+
+// Evaluates a generated tree, independently of the interpreter.
+fn reference(e: &Expr) -> Result<Value, String> {
+    match e {
+        Expr::Literal(t) => Ok(match t.ttype {
+            True => Value::Bool(true),
+            False => Value::Bool(false),
+            _ => t.literal.clone().unwrap(),
+        }),
+        Expr::Grouping(e) => reference(e),
+        Expr::Unary(op, e) => match (&op.ttype, reference(e)?) {
+            (Bang, v) => Ok(Value::Bool(!truthy(&v))),
+            (Minus, Value::Int(i64::MIN)) => Err("Integer overflow in unary -".to_string()),
+            (Minus, Value::Int(i)) => Ok(Value::Int(-i)),
+            (Tilde, Value::Int(i)) => Ok(Value::Int(!i)),
+            (_, v) => panic!("{} of {:?} isn't well-typed", op.lexeme, v),
+        },
+        Expr::Binary(l, op, r) => {
+            let l = reference(l)?;
+            let r = reference(r)?;
+            match (&op.ttype, l, r) {
+                (Comma, _, r) => Ok(r),
+                (EqualEqual, l, r) => Ok(Value::Bool(equal(&l, &r))),
+                (BangEqual, l, r) => Ok(Value::Bool(!equal(&l, &r))),
+                (Plus, Value::Str(ref l), Value::Str(ref r)) => {
+                    Ok(Value::Str(format!("{}{}", l, r)))
+                }
+                (ttype, Value::Int(l), Value::Int(r)) => int_binary(ttype, op.lexeme, l, r),
+                (_, l, r) => panic!("{:?} {} {:?} isn't well-typed", l, op.lexeme, r),
+            }
+        }
+        Expr::Conditional(c, a, b) => {
+            if truthy(&reference(c)?) {
+                reference(a)
+            } else {
+                reference(b)
+            }
+        }
+        Expr::List(_, items) => Ok(Value::list(
+            items.iter().map(reference).collect::<Result<_, _>>()?,
+        )),
+        Expr::Index(list, _, index) => match (reference(list)?, reference(index)?) {
+            (Value::List(ref items), Value::Int(i)) => Ok(items.borrow()[i as usize].clone()),
+            (l, i) => panic!("{:?}[{:?}] isn't well-typed", l, i),
+        },
+        _ => panic!("{:?} is never generated", e),
+    }
+}
+
+fn int_binary(ttype: &TokenType, lexeme: &str, l: i64, r: i64) -> Result<Value, String> {
+    let result = match ttype {
+        Less => return Ok(Value::Bool(l < r)),
+        LessEqual => return Ok(Value::Bool(l <= r)),
+        Greater => return Ok(Value::Bool(l > r)),
+        GreaterEqual => return Ok(Value::Bool(l >= r)),
+        Slash | Percent if r == 0 => return Err("Division by zero".to_string()),
+        LessLess | GreaterGreater if !(0..64).contains(&r) => {
+            return Err(format!("Shift amount out of range for {}", lexeme))
+        }
+        LessLess => Some(l << r),
+        GreaterGreater => Some(l >> r),
+        Ampersand => Some(l & r),
+        Pipe => Some(l | r),
+        Caret => Some(l ^ r),
+        Plus => l.checked_add(r),
+        Minus => l.checked_sub(r),
+        Star => l.checked_mul(r),
+        Slash => l.checked_div(r),
+        Percent => l.checked_rem(r),
+        StarStar => l.checked_pow(r as u32),
+        _ => panic!("{} is never generated between ints", lexeme),
+    };
+    result
+        .map(Value::Int)
+        .ok_or_else(|| format!("Integer overflow in {}", lexeme))
+}
+
+fn truthy(v: &Value) -> bool {
+    !matches!(v, Value::Bool(false) | Value::Nil)
+}
+
+fn equal(l: &Value, r: &Value) -> bool {
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => l == r,
+        (Value::Bool(l), Value::Bool(r)) => l == r,
+        (Value::Str(l), Value::Str(r)) => l == r,
+        (Value::List(l), Value::List(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| equal(l, r))
+        }
+        _ => false,
+    }
+}
+
+// Type and value, so that e.g. `1` and `1.0` aren't taken for the same result.
+fn describe(result: &Result<Value, String>) -> String {
+    match result {
+        Ok(v) => format!("{} {}", v.type_name(), v.repr()),
+        Err(msg) => format!("error: {}", msg),
+    }
+}
+
+fn parse_tree(source: &str) -> Result<String, String> {
+    let mut s = Scanner::new(source);
+    let tokens = s.scan_tokens().map_err(|e| e.to_string())?;
+    let mut p = Parser::new(tokens);
+    let stmts = p.parse().map_err(|e| e.to_string())?;
+    Ok(astprinter::dump_tree(stmts))
+}
+
+// What's wrong with the case generated from `seed`, if anything.
+fn check(seed: u64) -> Option<String> {
+    let mut rng = Rng::new(seed);
+    let ty = *rng.pick(TYPES);
+    let generated = expr(&mut rng, ty, MAX_DEPTH);
+    let source = astprinter::dump_ast(&generated);
+
+    let expected_tree = astprinter::dump_tree(&[Stmt::Expression(Box::new(generated.clone()))]);
+    match parse_tree(&source) {
+        Ok(tree) if tree == expected_tree => {}
+        Ok(tree) => {
+            return Some(format!(
+                "{}\nparsed back as\n{}instead of\n{}",
+                source, tree, expected_tree
+            ))
+        }
+        Err(e) => return Some(format!("{}\ndoesn't parse back: {}", source, e)),
+    }
+
+    let expected = describe(&reference(&generated));
+    for (name, backend) in BACKENDS {
+        let actual = describe(&backend(&source));
+        if actual != expected {
+            return Some(format!(
+                "{}\n{} gives {}\nexpected {}",
+                source, name, actual, expected
+            ));
+        }
+    }
+    None
+}
+
+#[test]
+fn expressions() {
+    let seed = env::var("LOX_SEED")
+        .ok()
+        .map_or(0, |s| s.parse().expect("LOX_SEED must be a number"));
+
+    // Of all failing cases, the shortest is the easiest to debug.
+    let failure = (seed..seed + CASES)
+        .filter_map(|seed| check(seed).map(|failure| (seed, failure)))
+        .min_by_key(|(_, failure)| failure.lines().next().map_or(0, str::len));
+    if let Some((seed, failure)) = failure {
+        panic!("case {} failed:\n{}", seed, failure);
+    }
+}
+
+// The generator reaches every kind of expression it knows about.
+#[test]
+fn coverage() {
+    let sources: Vec<String> = (0..CASES)
+        .map(|seed| {
+            let mut rng = Rng::new(seed);
+            let ty = *rng.pick(TYPES);
+            astprinter::dump_ast(&expr(&mut rng, ty, MAX_DEPTH))
+        })
+        .collect();
+    for fragment in &[
+        "?", ",", "!", "~", "**", "<<", "<=", "!=", ")[", "\"lox\"", "Division", "overflow",
+    ] {
+        let found = sources.iter().any(|s| s.contains(fragment))
+            || sources
+                .iter()
+                .any(|s| describe(&interpret(s)).contains(fragment));
+        assert!(found, "nothing generated with {:?}", fragment);
+    }
+}
@@ -0,0 +1,124 @@
+// Messages of the editor protocols, which are JSON objects sent over a
+// stream, each after a `Content-Length: N` header and a blank line.
+
+use super::map::OrderedMap;
+use super::stdlib::json;
+use super::value::Value;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+// The next message, or None once the input is closed.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    match read_body(input)? {
+        Some(body) => parse(&body).map(Some),
+        None => Ok(None),
+    }
+}
+
+// The next message as text, for reading on another thread than the one
+// handling it. Values can't be sent between threads.
+pub fn read_body(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid("Message without a Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| invalid(&e.to_string()))
+}
+
+pub fn parse(body: &str) -> io::Result<Value> {
+    json::parse(body).map_err(|e| invalid(&e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = json::stringify(message, None).map_err(|e| invalid(&e))?;
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// A JSON object with the given fields, in order.
+pub fn object(fields: Vec<(&str, Value)>) -> Value {
+    let mut map = OrderedMap::new();
+    for (name, value) in fields {
+        let _ = map.insert(string(name), value);
+    }
+    Value::map(map)
+}
+
+// Field `name` of an object, nil when it's missing or `v` isn't an object.
+pub fn get(v: &Value, name: &str) -> Value {
+    match v {
+        Value::Map(fields) => match fields.borrow().get(&string(name)) {
+            Ok(Some(value)) => value.clone(),
+            _ => Value::Nil,
+        },
+        _ => Value::Nil,
+    }
+}
+
+pub fn string(s: &str) -> Value {
+    Value::Str(s.to_string())
+}
+
+pub fn int(i: usize) -> Value {
+    Value::Int(i as i64)
+}
+
+pub fn as_str(v: &Value) -> Option<&str> {
+    match v {
+        Value::Str(s) => Some(s),
+        _ => None,
+    }
+}
+
+pub fn as_usize(v: &Value) -> Option<usize> {
+    match v {
+        Value::Int(i) => usize::try_from(*i).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let mut out = Vec::new();
+        let message = object(vec![("id", int(1)), ("method", string("ping"))]);
+        write_message(&mut out, &message).unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "Content-Length: 24\r\n\r\n{\"id\":1,\"method\":\"ping\"}"
+        );
+
+        let mut input = &out[..];
+        let read = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(as_str(&get(&read, "method")), Some("ping"));
+        assert_eq!(as_usize(&get(&read, "id")), Some(1));
+        assert_eq!(get(&read, "missing"), Value::Nil);
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        let mut headerless = &b"\r\n{}"[..];
+        assert!(read_message(&mut headerless).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+// Works out which declaration every variable name in a program refers to,
+// for editor tooling. Scopes follow the interpreter: blocks, `for` loops and
+// `catch` clauses open one, and a name refers to the innermost declaration
+// of it that came before.
+
+use super::ast::Expr;
+use super::parser::Stmt;
+use super::tokens::Token;
+use std::collections::HashMap;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    // The `as` name of an import.
+    Module,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // Byte offsets of the name where it's declared and everywhere it's used.
+    pub declaration: usize,
+    pub references: Vec<usize>,
+    // Bytes of the source from the declaration to the end of its scope.
+    pub scope: Range<usize>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Resolution {
+    pub symbols: Vec<Symbol>,
+    // Names used without being declared, e.g. built-in functions, and their offsets.
+    pub unresolved: Vec<(String, usize)>,
+}
+
+impl Symbol {
+    // Offsets of the declaration and then of every use.
+    pub fn occurrences(&self) -> impl Iterator<Item = usize> + '_ {
+        Some(self.declaration)
+            .into_iter()
+            .chain(self.references.iter().copied())
+    }
+
+    // Start of the occurrence of the name at `offset`, counting its end as part of it.
+    pub fn occurrence_at(&self, offset: usize) -> Option<usize> {
+        self.occurrences()
+            .find(|start| (*start..=start + self.name.len()).contains(&offset))
+    }
+}
+
+impl Resolution {
+    pub fn symbol_at(&self, offset: usize) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.occurrence_at(offset).is_some())
+    }
+
+    // Symbols that can be used at `offset`.
+    pub fn visible_at(&self, offset: usize) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(move |symbol| {
+            symbol.declaration + symbol.name.len() < offset && symbol.scope.contains(&offset)
+        })
+    }
+}
+
+pub fn resolve(source: &str, stmts: &[Stmt]) -> Resolution {
+    let mut resolver = Resolver {
+        source,
+        scopes: vec![(HashMap::new(), source.len())],
+        resolution: Resolution::default(),
+    };
+    resolver.stmts(stmts);
+    resolver.resolution
+}
+
+struct Resolver<'s> {
+    source: &'s str,
+    // Symbols declared in each enclosing scope by name, and where the scope ends.
+    scopes: Vec<(HashMap<&'s str, usize>, usize)>,
+    resolution: Resolution,
+}
+
+impl<'s> Resolver<'s> {
+    // Tokens borrow their lexemes from the source, so a token is where its lexeme is.
+    fn offset(&self, token: &Token) -> usize {
+        token.lexeme.as_ptr() as usize - self.source.as_ptr() as usize
+    }
+
+    fn scope_end(&self) -> usize {
+        self.scopes
+            .last()
+            .map_or(self.source.len(), |(_, end)| *end)
+    }
+
+    // Where the scope of a loop or catch clause with this body ends.
+    fn body_end(&self, body: &Stmt) -> usize {
+        match body {
+            Stmt::Block(_, _, close) => self.offset(close),
+            _ => self.scope_end(),
+        }
+    }
+
+    fn scoped(&mut self, end: usize, resolve: impl FnOnce(&mut Self)) {
+        self.scopes.push((HashMap::new(), end));
+        resolve(self);
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token<'s>, kind: SymbolKind) {
+        let declaration = self.offset(name);
+        let symbol = Symbol {
+            name: name.lexeme.to_string(),
+            kind,
+            declaration,
+            references: Vec::new(),
+            scope: declaration..self.scope_end(),
+        };
+        let index = self.resolution.symbols.len();
+        self.resolution.symbols.push(symbol);
+        if let Some((names, _)) = self.scopes.last_mut() {
+            names.insert(name.lexeme, index);
+        }
+    }
+
+    fn reference(&mut self, name: &Token<'s>) {
+        let offset = self.offset(name);
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|(names, _)| names.get(name.lexeme));
+        match found {
+            Some(&index) => self.resolution.symbols[index].references.push(offset),
+            None => self
+                .resolution
+                .unresolved
+                .push((name.lexeme.to_string(), offset)),
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt<'s>]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt<'s>) {
+        match stmt {
+            Stmt::Expression(e) | Stmt::Throw(_, e) => self.expr(e),
+            // The initializer can't see the variable it initializes.
+            Stmt::Var(name, init) => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                self.declare(name, SymbolKind::Variable);
+            }
+            Stmt::Block(_, stmts, close) => {
+                let end = self.offset(close);
+                self.scoped(end, |this| this.stmts(stmts));
+            }
+            // Along `else if` chains in a loop, they can be longer than the nesting limit.
+            Stmt::If(_, cond, then, otherwise) => {
+                let (mut cond, mut then, mut otherwise) = (cond, then, otherwise);
+                loop {
+                    self.expr(cond);
+                    self.stmt(then);
+                    match otherwise.as_deref() {
+                        Some(Stmt::If(_, c, t, o)) => {
+                            cond = c;
+                            then = t;
+                            otherwise = o;
+                        }
+                        Some(otherwise) => {
+                            self.stmt(otherwise);
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            Stmt::While(_, cond, body) => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            Stmt::For(_, init, cond, incr, body) => {
+                let end = self.body_end(body);
+                self.scoped(end, |this| {
+                    if let Some(init) = init {
+                        this.stmt(init);
+                    }
+                    for e in cond.iter().chain(incr) {
+                        this.expr(e);
+                    }
+                    this.stmt(body);
+                });
+            }
+            Stmt::ForIn(_, name, iterable, body) => {
+                self.expr(iterable);
+                let end = self.body_end(body);
+                self.scoped(end, |this| {
+                    this.declare(name, SymbolKind::Variable);
+                    this.stmt(body);
+                });
+            }
+            Stmt::Import(_, _, alias, names) => {
+                if let Some(alias) = alias {
+                    self.declare(alias, SymbolKind::Module);
+                }
+                for (_, local) in names {
+                    self.declare(local, SymbolKind::Variable);
+                }
+            }
+            Stmt::Export(_, var) => self.stmt(var),
+            Stmt::Try(_, body, catch, finally) => {
+                self.stmt(body);
+                if let Some((name, handler)) = catch {
+                    let end = self.body_end(handler);
+                    self.scoped(end, |this| {
+                        this.declare(name, SymbolKind::Variable);
+                        this.stmt(handler);
+                    });
+                }
+                if let Some(finally) = finally {
+                    self.stmt(finally);
+                }
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr<'s>) {
+        match expr {
+            Expr::Identifier(name) => self.reference(name),
+            Expr::Literal(_) => {}
+            Expr::Grouping(e) | Expr::Unary(_, e) | Expr::Get(e, _) => self.expr(e),
+            Expr::Binary(l, _, r) | Expr::Index(l, _, r) => {
+                self.expr(l);
+                self.expr(r);
+            }
+            Expr::Conditional(c, a, b) | Expr::SetIndex(c, a, _, b) => {
+                for e in &[c, a, b] {
+                    self.expr(e);
+                }
+            }
+            Expr::Assign(name, _, value) => {
+                self.expr(value);
+                self.reference(name);
+            }
+            Expr::List(_, items) | Expr::Interpolation(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Expr::Map(_, entries) => {
+                for (k, v) in entries {
+                    self.expr(k);
+                    self.expr(v);
+                }
+            }
+            Expr::Call(callee, _, args) => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::Parser;
+    use super::super::scanner::Scanner;
+    use super::*;
+
+    fn resolved(source: &str) -> Resolution {
+        let mut s = Scanner::new(source);
+        let tokens = s.scan_tokens().unwrap();
+        let mut p = Parser::new(tokens);
+        let stmts = p.parse().unwrap();
+        resolve(source, stmts)
+    }
+
+    // Each symbol as `name@declaration: references`.
+    fn summary(r: &Resolution) -> Vec<String> {
+        r.symbols
+            .iter()
+            .map(|s| format!("{}@{}: {:?}", s.name, s.declaration, s.references))
+            .collect()
+    }
+
+    #[test]
+    fn scopes() {
+        let source = "var a = 1;\n{ var a = a; a; }\na;";
+        let r = resolved(source);
+        assert_eq!(summary(&r), ["a@4: [21, 29]", "a@17: [24]"]);
+        assert_eq!(r.symbols[1].scope, 17..27);
+        assert_eq!(r.symbol_at(25).map(|s| s.declaration), Some(17));
+        assert_eq!(r.symbol_at(3), None);
+        assert_eq!(r.symbols[0].occurrence_at(30), Some(29));
+
+        let visible: Vec<usize> = r.visible_at(24).map(|s| s.declaration).collect();
+        assert_eq!(visible, [4, 17]);
+        assert_eq!(r.visible_at(2).count(), 0);
+    }
+
+    #[test]
+    fn declarations() {
+        let r = resolved(
+            "import \"math\" as m;\n\
+             for (var i = 0; i < 3; i += 1) m.abs(i);\n\
+             for (var x in [1]) { x; }\n\
+             try { throw 1; } catch (e) { e; }\n\
+             str(x);",
+        );
+        let names: Vec<(&str, SymbolKind, usize)> = r
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.references.len()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("m", SymbolKind::Module, 1),
+                ("i", SymbolKind::Variable, 3),
+                ("x", SymbolKind::Variable, 1),
+                ("e", SymbolKind::Variable, 1),
+            ]
+        );
+        let unresolved: Vec<&str> = r.unresolved.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(unresolved, ["str", "x"]);
+    }
+}
@@ -13,6 +13,11 @@ pub struct Scanner<'a> {
     pub current: usize,
     pub line: usize,
     pub tokens: Vec<Token<'a>>,
+    // When set, whitespace and comments are kept as trivia on the following token.
+    pub lossless: bool,
+    trivia: Vec<Trivia<'a>>,
+    // Brace depth of each string interpolation we are currently inside of.
+    interpolations: Vec<usize>,
     keywords: HashMap<&'static str, TokenType>,
 }
 
@@ -21,100 +26,188 @@ impl<'a> Scanner<'a> {
         let mut keywords = HashMap::new();
 
         keywords.insert("and", And);
+        keywords.insert("break", Break);
+        keywords.insert("catch", Catch);
         keywords.insert("class", Class);
+        keywords.insert("continue", Continue);
         keywords.insert("else", Else);
+        keywords.insert("export", Export);
         keywords.insert("false", False);
+        keywords.insert("finally", Finally);
         keywords.insert("for", For);
         keywords.insert("fun", Fun);
         keywords.insert("if", If);
+        keywords.insert("import", Import);
+        keywords.insert("in", In);
         keywords.insert("nil", Nil);
         keywords.insert("or", Or);
         keywords.insert("print", Print);
         keywords.insert("return", Return);
         keywords.insert("super", Super);
         keywords.insert("this", This);
+        keywords.insert("throw", Throw);
         keywords.insert("true", True);
+        keywords.insert("try", Try);
         keywords.insert("var", Var);
         keywords.insert("while", While);
 
         Scanner {
-            source: source,
+            source,
             start: 0,
             current: 0,
             line: 1,
             tokens: Vec::new(),
-            keywords: keywords,
+            lossless: false,
+            trivia: Vec::new(),
+            interpolations: Vec::new(),
+            keywords,
         }
     }
 
-    pub fn scan_tokens(self: &mut Self) -> Result<&Vec<Token>, CompileError> {
+    pub fn lossless(source: &'a str) -> Self {
+        Scanner {
+            lossless: true,
+            ..Scanner::new(source)
+        }
+    }
+
+    pub fn scan_tokens(&mut self) -> Result<&Vec<Token<'_>>, CompileError> {
         while !self.is_at_end() {
             self.start = self.current;
             self.scan_token()?;
         }
 
+        if !self.interpolations.is_empty() {
+            return self.build_error(UnterminatedString, "");
+        }
+
         self.tokens.push(Token {
             ttype: Eof,
             lexeme: "",
             literal: None,
             line: self.line,
+            trivia: std::mem::take(&mut self.trivia),
         });
 
         Ok(&self.tokens)
     }
 
-    fn is_at_end(self: &Self) -> bool {
+    fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
-    fn scan_token(self: &mut Self) -> Result<(), CompileError> {
+    fn scan_token(&mut self) -> Result<(), CompileError> {
         let c = self.advance();
         match c {
             '(' => self.add_token(LeftParen),
             ')' => self.add_token(RightParen),
-            '{' => self.add_token(LeftBrace),
-            '}' => self.add_token(RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(LeftBrace);
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    // End of `${...}`, the rest of the string follows.
+                    self.interpolations.pop();
+                    self.scan_string()?;
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(RightBrace);
+                }
+                None => self.add_token(RightBrace),
+            },
+            '[' => self.add_token(LeftBracket),
+            ']' => self.add_token(RightBracket),
             ',' => self.add_token(Comma),
             '.' => self.add_token(Dot),
-            '-' => self.add_token(Minus),
-            '+' => self.add_token(Plus),
+            '-' => {
+                let next_eq = self.advance_if_match('=');
+                self.add_token(if next_eq { MinusEqual } else { Minus });
+            }
+            '+' => {
+                let next_eq = self.advance_if_match('=');
+                self.add_token(if next_eq { PlusEqual } else { Plus });
+            }
             ';' => self.add_token(Semicolon),
-            '*' => self.add_token(Star),
+            '?' => self.add_token(Question),
+            ':' => self.add_token(Colon),
+            '*' => {
+                if self.advance_if_match('*') {
+                    self.add_token(StarStar);
+                } else if self.advance_if_match('=') {
+                    self.add_token(StarEqual);
+                } else {
+                    self.add_token(Star);
+                }
+            }
+            '%' => self.add_token(Percent),
+            '&' => self.add_token(Ampersand),
+            '|' => self.add_token(Pipe),
+            '^' => self.add_token(Caret),
+            '~' => self.add_token(Tilde),
             '!' => {
                 let next_eq = self.advance_if_match('=');
                 self.add_token(if next_eq { BangEqual } else { Bang });
             }
             '=' => {
                 let next_eq = self.advance_if_match('=');
-                self.add_token(if next_eq { EqualEqual } else { Bang });
+                self.add_token(if next_eq { EqualEqual } else { Equal });
             }
             '<' => {
-                let next_eq = self.advance_if_match('=');
-                self.add_token(if next_eq { LessEqual } else { Less });
+                if self.advance_if_match('<') {
+                    self.add_token(LessLess);
+                } else if self.advance_if_match('=') {
+                    self.add_token(LessEqual);
+                } else {
+                    self.add_token(Less);
+                }
             }
             '>' => {
-                let next_eq = self.advance_if_match('=');
-                self.add_token(if next_eq { GreaterEqual } else { Greater });
+                if self.advance_if_match('>') {
+                    self.add_token(GreaterGreater);
+                } else if self.advance_if_match('=') {
+                    self.add_token(GreaterEqual);
+                } else {
+                    self.add_token(Greater);
+                }
             }
             '/' => {
                 let next_slash = self.advance_if_match('/');
                 if next_slash {
-                    while self.peek() != '\n' && !self.is_at_end() {
+                    // The \r of a \r\n line ending is left out of the comment text.
+                    while self.peek() != '\n'
+                        && !(self.peek() == '\r' && self.peek_next() == '\n')
+                        && !self.is_at_end()
+                    {
                         self.advance();
                     }
+                    self.add_trivia(TriviaKind::Comment, self.line);
+                } else if self.advance_if_match('*') {
+                    self.scan_block_comment()?;
+                } else if self.advance_if_match('=') {
+                    self.add_token(SlashEqual);
                 } else {
                     self.add_token(Slash);
                 }
             }
-            ' ' | '\r' | '\t' => {}
+            ' ' | '\r' | '\t' => {
+                while let ' ' | '\r' | '\t' = self.peek() {
+                    self.advance();
+                }
+                self.add_trivia(TriviaKind::Whitespace, self.line);
+            }
             '\n' => {
+                self.add_trivia(TriviaKind::Newline, self.line);
                 self.line += 1;
             }
             '"' => {
                 self.scan_string()?;
             }
             '0'..='9' => {
-                self.scan_number();
+                self.scan_number()?;
             }
             'a'..='z' | 'A'..='Z' | '_' => {
                 self.scan_identifier();
@@ -127,11 +220,7 @@ impl<'a> Scanner<'a> {
         Ok(())
     }
 
-    fn build_error(
-        self: &Self,
-        error_type: CompileErrorType,
-        msg: &str,
-    ) -> Result<(), CompileError> {
+    fn build_error<T>(&self, error_type: CompileErrorType, msg: &str) -> Result<T, CompileError> {
         Err(CompileError {
             err: error_type,
             line: self.line,
@@ -140,86 +229,204 @@ impl<'a> Scanner<'a> {
             } else {
                 Some(msg.to_string())
             },
+            at: None,
         })
     }
 
-    fn advance(self: &mut Self) -> char {
-        self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+    // `current` is a byte offset into `source`, so lexemes can be sliced directly.
+    // Returns '\0' without moving at the end of the source, like `peek`.
+    fn advance(&mut self) -> char {
+        match self.source[self.current..].chars().next() {
+            Some(c) => {
+                self.current += c.len_utf8();
+                c
+            }
+            None => '\0',
+        }
     }
 
-    fn advance_if_match(self: &mut Self, c: char) -> bool {
-        if self.is_at_end() || self.source.chars().nth(self.current).unwrap() != c {
+    fn advance_if_match(&mut self, c: char) -> bool {
+        if self.is_at_end() || self.peek() != c {
             return false;
         }
 
-        self.current += 1;
+        self.current += c.len_utf8();
         true
     }
 
-    fn peek(self: &mut Self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current).unwrap()
+    fn peek(&mut self) -> char {
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
-    fn peek_next(self: &mut Self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current + 1).unwrap()
+    fn peek_next(&mut self) -> char {
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
-    fn scan_string(self: &mut Self) -> Result<(), CompileError> {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+    // Scans the rest of a string literal. A `${` ends the current part with an
+    // Interpolation token; the string resumes when the matching `}` is reached.
+    fn scan_string(&mut self) -> Result<(), CompileError> {
+        let mut value = String::new();
+
+        loop {
+            if self.is_at_end() {
+                return self.build_error(UnterminatedString, "");
+            }
+
+            match self.advance() {
+                '"' => break,
+                '$' if self.peek() == '{' => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    self.add_token_with_literal(Interpolation, Some(Value::Str(value)));
+                    return Ok(());
+                }
+                '\\' => {
+                    let c = self.scan_escape()?;
+                    value.push(c);
+                }
+                c => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    value.push(c);
+                }
             }
-            self.advance();
         }
 
+        self.add_token_with_literal(Str, Some(Value::Str(value)));
+
+        Ok(())
+    }
+
+    fn scan_escape(&mut self) -> Result<char, CompileError> {
+        let start = self.current - 1;
         if self.is_at_end() {
             return self.build_error(UnterminatedString, "");
         }
 
-        // close "
-        self.advance();
+        match self.advance() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '"' => Ok('"'),
+            '\\' => Ok('\\'),
+            '$' => Ok('$'),
+            'u' if self.advance_if_match('{') => {
+                let digits_start = self.current;
+                while self.peek().is_ascii_hexdigit() {
+                    self.advance();
+                }
+                let digits = &self.source[digits_start..self.current];
+
+                if self.advance_if_match('}') && digits.len() <= 6 {
+                    if let Some(c) = u32::from_str_radix(digits, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                    {
+                        return Ok(c);
+                    }
+                }
+                self.build_error(InvalidEscape, &self.source[start..self.current])
+            }
+            _ => self.build_error(InvalidEscape, &self.source[start..self.current]),
+        }
+    }
 
-        self.add_token_with_literal(
-            Str,
-            Some(Value::Str(
-                self.source[self.start + 1..self.current - 1].to_string(),
-            )),
-        );
+    // Block comments nest, so `/* a /* b */ c */` is a single comment.
+    fn scan_block_comment(&mut self) -> Result<(), CompileError> {
+        let line = self.line;
+        let mut depth = 1;
 
+        while depth > 0 {
+            if self.is_at_end() {
+                return self.build_error(UnterminatedComment, "");
+            }
+
+            match self.advance() {
+                '/' if self.peek() == '*' => {
+                    self.advance();
+                    depth += 1;
+                }
+                '*' if self.peek() == '/' => {
+                    self.advance();
+                    depth -= 1;
+                }
+                '\n' => self.line += 1,
+                _ => {}
+            }
+        }
+
+        self.add_trivia(TriviaKind::Comment, line);
         Ok(())
     }
 
-    fn scan_number(self: &mut Self) {
-        while self.peek().is_ascii_digit() {
+    // Numbers are decimal with an optional fraction and exponent (`1_000.5e-3`),
+    // or integers with a `0x`/`0b` prefix. `_` may separate digits.
+    fn scan_number(&mut self) -> Result<(), CompileError> {
+        let radix = match (&self.source[self.start..self.current], self.peek()) {
+            ("0", 'x') | ("0", 'X') => 16,
+            ("0", 'b') | ("0", 'B') => 2,
+            _ => 10,
+        };
+
+        if radix != 10 {
             self.advance();
         }
+        let digits_start = self.current;
+        self.scan_digits(radix);
 
-        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
-            self.advance();
+        if radix == 10 {
+            if self.peek() == '.' && self.peek_next().is_ascii_digit() {
+                self.advance();
+                self.scan_digits(10);
+            }
 
-            while self.peek().is_ascii_digit() {
+            if let 'e' | 'E' = self.peek() {
                 self.advance();
+                if let '+' | '-' = self.peek() {
+                    self.advance();
+                }
+                self.scan_digits(10);
             }
         }
 
-        self.add_token_with_literal(
-            Number,
-            Some(Value::Number(
-                self.source[self.start..self.current]
-                    .parse::<f64>()
-                    .unwrap(),
-            )),
-        );
+        // A literal running straight into letters, like `0b12` or `1e`, is malformed as a whole.
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
+            self.advance();
+        }
+
+        let lexeme = &self.source[self.start..self.current];
+        let digits: String = if radix == 10 {
+            lexeme.replace('_', "")
+        } else {
+            self.source[digits_start..self.current].replace('_', "")
+        };
+
+        // Literals without a fraction or exponent are integers and must fit in an i64.
+        let value = if !valid_underscores(lexeme, radix) {
+            None
+        } else if radix == 10 && digits.contains(['.', 'e', 'E']) {
+            digits.parse::<f64>().ok().map(Value::Number)
+        } else {
+            i64::from_str_radix(&digits, radix).ok().map(Value::Int)
+        };
+
+        match value {
+            Some(v) => {
+                self.add_token_with_literal(Number, Some(v));
+                Ok(())
+            }
+            None => self.build_error(InvalidNumber, lexeme),
+        }
     }
 
-    fn scan_identifier(self: &mut Self) {
+    fn scan_digits(&mut self, radix: u32) {
+        while self.peek().is_digit(radix) || self.peek() == '_' {
+            self.advance();
+        }
+    }
+
+    fn scan_identifier(&mut self) {
         loop {
             let c = self.peek();
             if !c.is_alphanumeric() && c != '_' {
@@ -234,18 +441,40 @@ impl<'a> Scanner<'a> {
         self.add_token(ttype);
     }
 
-    fn add_token(self: &mut Self, ttype: TokenType) {
+    fn add_token(&mut self, ttype: TokenType) {
         self.add_token_with_literal(ttype, None)
     }
 
-    fn add_token_with_literal(self: &mut Self, ttype: TokenType, literal: Option<Value>) {
+    fn add_token_with_literal(&mut self, ttype: TokenType, literal: Option<Value>) {
         self.tokens.push(Token {
-            ttype: ttype,
+            ttype,
             lexeme: &self.source[self.start..self.current],
             literal,
             line: self.line,
+            trivia: std::mem::take(&mut self.trivia),
         });
     }
+
+    fn add_trivia(&mut self, kind: TriviaKind, line: usize) {
+        if self.lossless {
+            self.trivia.push(Trivia {
+                kind,
+                text: &self.source[self.start..self.current],
+                line,
+            });
+        }
+    }
+}
+
+// Every `_` in a number has to sit between two digits.
+fn valid_underscores(lexeme: &str, radix: u32) -> bool {
+    let chars: Vec<char> = lexeme.chars().collect();
+    chars.iter().enumerate().all(|(i, &c)| {
+        c != '_'
+            || (i > 0
+                && chars[i - 1].is_digit(radix)
+                && chars.get(i + 1).is_some_and(|n| n.is_digit(radix)))
+    })
 }
 
 #[cfg(test)]
@@ -278,22 +507,224 @@ mod tests {
         assert_eq!(scan_types("else or lol"), vec![Else, Or, Identifier, Eof]);
     }
 
+    #[test]
+    fn operators() {
+        assert_eq!(
+            scan_types("= == ** * % & | ^ ~ << <= < >> >= >"),
+            vec![
+                Equal,
+                EqualEqual,
+                StarStar,
+                Star,
+                Percent,
+                Ampersand,
+                Pipe,
+                Caret,
+                Tilde,
+                LessLess,
+                LessEqual,
+                Less,
+                GreaterGreater,
+                GreaterEqual,
+                Greater,
+                Eof
+            ]
+        );
+        assert_eq!(
+            scan_types("[a][0]"),
+            vec![
+                LeftBracket,
+                Identifier,
+                RightBracket,
+                LeftBracket,
+                Number,
+                RightBracket,
+                Eof
+            ]
+        );
+        assert_eq!(
+            scan_types("a ? b : c"),
+            vec![Identifier, Question, Identifier, Colon, Identifier, Eof]
+        );
+        assert_eq!(
+            scan_types("+= -= *= /= +-"),
+            vec![PlusEqual, MinusEqual, StarEqual, SlashEqual, Plus, Minus, Eof]
+        );
+    }
+
+    fn scan_strings(msg: &str) -> Vec<Value> {
+        let mut s = Scanner::new(msg);
+        let tokens = s.scan_tokens().unwrap();
+        tokens.iter().filter_map(|t| t.literal.clone()).collect()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            scan_strings(r#""a\tb\n\"c\"\\\$" "\u{48}\u{1F600}""#),
+            vec![
+                Value::Str("a\tb\n\"c\"\\$".to_string()),
+                Value::Str("H\u{1F600}".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_eq!(
+            scan_types(r#""a${1 + "b${x}"}c" "${ {} }""#),
+            vec![
+                Interpolation,
+                Number,
+                Plus,
+                Interpolation,
+                Identifier,
+                Str,
+                Str,
+                Interpolation,
+                LeftBrace,
+                RightBrace,
+                Str,
+                Eof
+            ]
+        );
+
+        let source = "\"${1}${2}\"";
+        let mut s = Scanner::lossless(source);
+        let tokens = s.scan_tokens().unwrap();
+        let lexemes: Vec<&str> = tokens.iter().map(|t| t.lexeme).collect();
+        assert_eq!(lexemes, vec!["\"${", "1", "}${", "2", "}\"", ""]);
+        assert_eq!(source_text(tokens), source);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            scan_strings("12 1.5 0x1F 0XfF 0b101 1_000_000 9007199254740993"),
+            vec![
+                Value::Int(12),
+                Value::Number(1.5),
+                Value::Int(31),
+                Value::Int(255),
+                Value::Int(5),
+                Value::Int(1_000_000),
+                Value::Int(9007199254740993)
+            ]
+        );
+        assert_eq!(
+            scan_strings("1e3 2.5E-2 1e+2 1.0"),
+            vec![1e3, 0.025, 100., 1.]
+                .into_iter()
+                .map(Value::Number)
+                .collect::<Vec<Value>>()
+        );
+        assert!(matches!(scan_strings("1.0")[0], Value::Number(_)));
+        assert_eq!(scan_types("1.foo"), vec![Number, Dot, Identifier, Eof]);
+        assert_eq!(scan_types("1-2"), vec![Number, Minus, Number, Eof]);
+
+        for bad in &[
+            "1e",
+            "1e+",
+            "0x",
+            "0b",
+            "0b12",
+            "12abc",
+            "1_",
+            "1__0",
+            "1_.5",
+            "0x_1",
+            "9223372036854775808",
+            "0xffff_ffff_ffff_ffff",
+        ] {
+            assert_eq!(
+                scan_error(bad),
+                CompileError {
+                    err: InvalidNumber,
+                    line: 1,
+                    text: Some(bad.to_string()),
+                    at: None,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_eq!(
+            scan_types("1/*2*/3/4"),
+            vec![Number, Number, Slash, Number, Eof]
+        );
+        assert_eq!(
+            scan_types("(/* a /* b */ c */)"),
+            vec![LeftParen, RightParen, Eof]
+        );
+        assert_eq!(scan_types("/**/*/**/"), vec![Star, Eof]);
+
+        let mut s = Scanner::lossless("/* a\n/*\n*/ b */ 1");
+        let tokens = s.scan_tokens().unwrap();
+        assert_eq!(tokens[0].line, 3);
+        assert_eq!(tokens[0].trivia[0].text, "/* a\n/*\n*/ b */");
+        assert_eq!(tokens[0].trivia[0].line, 1);
+    }
+
+    #[test]
+    fn lossless() {
+        let source = "// héllo\n(1 +\t\"ü\") // end\r\n";
+        let mut s = Scanner::lossless(source);
+        let tokens = s.scan_tokens().unwrap();
+        assert_eq!(source_text(tokens), source);
+
+        let kinds: Vec<TriviaKind> = tokens[0].trivia.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TriviaKind::Comment, TriviaKind::Newline]);
+        assert_eq!(tokens[0].trivia[0].text, "// héllo");
+        assert_eq!(tokens[5].ttype, Eof);
+        assert_eq!(tokens[5].trivia[1].text, "// end");
+        assert_eq!(tokens[5].trivia[1].line, 2);
+
+        let mut s = Scanner::new(source);
+        let tokens = s.scan_tokens().unwrap();
+        assert!(tokens.iter().all(|t| t.trivia.is_empty()));
+    }
+
     #[test]
     fn unexpected_char_error() {
         assert_eq!(
-            scan_error("\n((\n(~"),
+            scan_error("\n((\n(@"),
             CompileError {
-                err: UnexpectedChar('~'),
+                err: UnexpectedChar('@'),
                 line: 3,
-                text: None
+                text: None,
+                at: None,
+            }
+        );
+        assert_eq!(
+            scan_error("1 /* a /* b */\n"),
+            CompileError {
+                err: UnterminatedComment,
+                line: 2,
+                text: None,
+                at: None,
+            }
+        );
+        assert_eq!(
+            scan_error("\"a\\qb\""),
+            CompileError {
+                err: InvalidEscape,
+                line: 1,
+                text: Some("\\q".to_string()),
+                at: None,
             }
         );
+        assert_eq!(scan_error("\"\\u{110000}\"").err, InvalidEscape);
+        assert_eq!(scan_error("\"\\u{}\"").err, InvalidEscape);
+        assert_eq!(scan_error("\"${1\n").err, UnterminatedString);
         assert_eq!(
             scan_error("(\"===adsdas\n"),
             CompileError {
                 err: UnterminatedString,
                 line: 2,
-                text: None
+                text: None,
+                at: None,
             }
         );
     }
@@ -0,0 +1,108 @@
+// Golden-file tests of the front end. Next to each `tests/snapshots/NAME.lox`
+// are its expected token dump, syntax tree and error message, in NAME.tokens,
+// NAME.ast and NAME.err. Outputs that would be empty have no file, e.g. a
+// source that doesn't scan has no tokens or tree.
+//
+// Run with LOX_BLESS=1 to write the current outputs instead of comparing,
+// then review the changes with `git diff tests/snapshots`.
+
+use super::astprinter;
+use super::parser::Parser;
+use super::scanner::Scanner;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The outputs for `source`, keyed by file extension.
+fn outputs(source: &str) -> Vec<(&'static str, Option<String>)> {
+    let mut s = Scanner::new(source);
+    let (tokens, ast, err) = match s.scan_tokens() {
+        Ok(tokens) => {
+            let dump: String = tokens
+                .iter()
+                .map(|t| {
+                    let mut line = format!("{} {:?} {:?}", t.line, t.ttype, t.lexeme);
+                    if let Some(literal) = &t.literal {
+                        line.push_str(&format!(" {}", literal.repr()));
+                    }
+                    line + "\n"
+                })
+                .collect();
+            let mut p = Parser::new(tokens);
+            match p.parse() {
+                Ok(stmts) => (Some(dump), Some(astprinter::dump_tree(stmts)), None),
+                Err(e) => (Some(dump), None, Some(e.to_string() + "\n")),
+            }
+        }
+        Err(e) => (None, None, Some(e.to_string() + "\n")),
+    };
+    vec![("tokens", tokens), ("ast", ast), ("err", err)]
+}
+
+// The lines that differ, marked `-` for expected and `+` for actual.
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<&str>, Vec<&str>) =
+        (expected.lines().collect(), actual.lines().collect());
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(i), actual.get(i));
+        if e != a {
+            if let Some(e) = e {
+                out.push_str(&format!("  {:>4} - {}\n", i + 1, e));
+            }
+            if let Some(a) = a {
+                out.push_str(&format!("  {:>4} + {}\n", i + 1, a));
+            }
+        }
+    }
+    out
+}
+
+fn inputs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "lox"))
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+#[test]
+fn snapshots() {
+    let bless = env::var_os("LOX_BLESS").is_some();
+    let mut failures = Vec::new();
+
+    for input in inputs() {
+        let source = fs::read_to_string(&input).unwrap();
+        for (extension, actual) in outputs(&source) {
+            let path = input.with_extension(extension);
+            let expected = fs::read_to_string(&path).ok();
+            if expected == actual {
+                continue;
+            }
+
+            if bless {
+                match &actual {
+                    Some(actual) => fs::write(&path, actual).unwrap(),
+                    None => fs::remove_file(&path).unwrap(),
+                }
+                continue;
+            }
+            let name = path.file_name().unwrap().to_string_lossy();
+            failures.push(match (expected, actual) {
+                (Some(e), Some(a)) => format!("{} differs:\n{}", name, diff(&e, &a)),
+                (None, Some(a)) => format!("{} is missing, expected:\n{}", name, a),
+                (Some(_), None) => format!("{} should not exist", name),
+                (None, None) => unreachable!(),
+            });
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\nRun with LOX_BLESS=1 to accept the new outputs.",
+        failures.join("\n")
+    );
+}
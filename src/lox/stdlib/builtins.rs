@@ -0,0 +1,60 @@
+use super::super::methods::expect_args;
+use super::super::value::Value;
+use super::add;
+use std::collections::HashMap;
+
+pub fn exports() -> HashMap<String, Value> {
+    let mut exports = HashMap::new();
+
+    add(&mut exports, "str", |args| {
+        let [x] = expect_args("str", args)?;
+        Ok(Value::Str(x.to_string()))
+    });
+    add(&mut exports, "num", |args| {
+        let [x] = expect_args("num", args)?;
+        match &x {
+            Value::Int(_) | Value::Number(_) => Ok(x),
+            Value::Str(s) => parse_number(s),
+            _ => Err(format!("num() expects a string, not {}", x.type_name())),
+        }
+    });
+
+    exports
+}
+
+// Accepts decimal numbers with an optional sign, fraction and exponent, like
+// "-2.5e3", and surrounding whitespace. Hex and binary literals aren't numbers
+// here. Integers stay ints unless they overflow.
+fn parse_number(s: &str) -> Result<Value, String> {
+    let text = s.trim();
+    let is_numeric = |c: char| c.is_ascii_digit() || ".eE+-".contains(c);
+    if !text.chars().any(|c| c.is_ascii_digit()) || !text.chars().all(is_numeric) {
+        return Err(format!("Invalid number {:?}", s));
+    }
+
+    match text.parse::<i64>() {
+        Ok(i) => Ok(Value::Int(i)),
+        Err(_) => text
+            .parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| format!("Invalid number {:?}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        let n = |s: &str| parse_number(s);
+        assert_eq!(n("42"), Ok(Value::Int(42)));
+        assert_eq!(n(" -7\n"), Ok(Value::Int(-7)));
+        assert_eq!(n("2.5"), Ok(Value::Number(2.5)));
+        assert_eq!(n("1e3"), Ok(Value::Number(1000.)));
+        assert_eq!(n("99999999999999999999"), Ok(Value::Number(1e20)));
+        for bad in &["", "abc", "1.2.3", "inf", "NaN", "--1", "e", "0x10"] {
+            assert!(n(bad).is_err(), "{:?} parsed", bad);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use super::super::methods::expect_args;
+use super::super::value::Value;
+use super::{add, int, string};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::process;
+
+// What scripts are allowed to touch outside the interpreter. The builtins
+// are always defined, so a disabled one fails with a runtime error instead
+// of looking like a typo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities {
+    // readFile, writeFile, appendFile, listDir, exists and importing files.
+    pub files: bool,
+    // env.
+    pub env: bool,
+    // readLine and exit.
+    pub process: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities {
+            files: true,
+            env: true,
+            process: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Capabilities {
+            files: false,
+            env: false,
+            process: false,
+        }
+    }
+}
+
+pub fn exports(capabilities: Capabilities) -> HashMap<String, Value> {
+    let mut exports = HashMap::new();
+    let mut gated = |name: &'static str,
+                     allowed: bool,
+                     what: &'static str,
+                     f: fn(Vec<Value>) -> Result<Value, String>| {
+        add(&mut exports, name, move |args| {
+            if allowed {
+                f(args)
+            } else {
+                Err(format!(
+                    "{}() needs {} access, which is disabled",
+                    name, what
+                ))
+            }
+        })
+    };
+
+    gated("readFile", capabilities.files, "file", |args| {
+        let [path] = expect_args("readFile", args)?;
+        let path = string("readFile", &path)?;
+        fs::read_to_string(path)
+            .map(Value::Str)
+            .map_err(|e| format!("readFile(): {}: {}", path, e))
+    });
+    gated("writeFile", capabilities.files, "file", |args| {
+        let [path, text] = expect_args("writeFile", args)?;
+        let path = string("writeFile", &path)?;
+        fs::write(path, string("writeFile", &text)?)
+            .map(|_| Value::Nil)
+            .map_err(|e| format!("writeFile(): {}: {}", path, e))
+    });
+    gated("appendFile", capabilities.files, "file", |args| {
+        let [path, text] = expect_args("appendFile", args)?;
+        let (path, text) = (string("appendFile", &path)?, string("appendFile", &text)?);
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .and_then(|mut f| f.write_all(text.as_bytes()))
+            .map(|_| Value::Nil)
+            .map_err(|e| format!("appendFile(): {}: {}", path, e))
+    });
+    // Names of the entries in a directory, sorted.
+    gated("listDir", capabilities.files, "file", |args| {
+        let [path] = expect_args("listDir", args)?;
+        let path = string("listDir", &path)?;
+        let mut names = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+                    .collect::<io::Result<Vec<String>>>()
+            })
+            .map_err(|e| format!("listDir(): {}: {}", path, e))?;
+        names.sort();
+        Ok(Value::list(names.into_iter().map(Value::Str).collect()))
+    });
+    gated("exists", capabilities.files, "file", |args| {
+        let [path] = expect_args("exists", args)?;
+        Ok(Value::Bool(fs::metadata(string("exists", &path)?).is_ok()))
+    });
+
+    // The value of an environment variable, or nil if it isn't set.
+    gated("env", capabilities.env, "environment", |args| {
+        let [name] = expect_args("env", args)?;
+        Ok(env::var(string("env", &name)?).map_or(Value::Nil, Value::Str))
+    });
+
+    // The next line of stdin without its line ending, or nil at the end of input.
+    gated("readLine", capabilities.process, "process", |args| {
+        let [] = expect_args("readLine", args)?;
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => Ok(Value::Nil),
+            Ok(_) => {
+                let end = line.trim_end_matches(&['\n', '\r'][..]).len();
+                line.truncate(end);
+                Ok(Value::Str(line))
+            }
+            Err(e) => Err(format!("readLine(): {}", e)),
+        }
+    });
+    gated("exit", capabilities.process, "process", |args| {
+        let [code] = expect_args("exit", args)?;
+        let code = int("exit", &code)?;
+        let code =
+            i32::try_from(code).map_err(|_| format!("exit() code {} is out of range", code))?;
+        let _ = io::stdout().flush();
+        process::exit(code)
+    });
+
+    exports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(
+        exports: &HashMap<String, Value>,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, String> {
+        match &exports[name] {
+            Value::Native(f) => (f.function)(args),
+            v => panic!("{} is not a function: {}", name, v),
+        }
+    }
+
+    #[test]
+    fn files() {
+        let dir = env::temp_dir().join(format!("lox-io-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let s = |s: &str| Value::Str(s.to_string());
+        let path = s(dir.join("a.txt").to_str().unwrap());
+        let m = exports(Capabilities::all());
+
+        assert_eq!(
+            call(&m, "exists", vec![path.clone()]),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            call(&m, "writeFile", vec![path.clone(), s("ä\n")]),
+            Ok(Value::Nil)
+        );
+        assert_eq!(
+            call(&m, "appendFile", vec![path.clone(), s("b")]),
+            Ok(Value::Nil)
+        );
+        assert_eq!(call(&m, "readFile", vec![path.clone()]), Ok(s("ä\nb")));
+        assert_eq!(
+            call(&m, "exists", vec![path.clone()]),
+            Ok(Value::Bool(true))
+        );
+        fs::write(dir.join("0.txt"), "").unwrap();
+        assert_eq!(
+            call(&m, "listDir", vec![s(dir.to_str().unwrap())])
+                .unwrap()
+                .to_string(),
+            "[\"0.txt\", \"a.txt\"]"
+        );
+        assert!(call(&m, "readFile", vec![s(dir.join("nope").to_str().unwrap())]).is_err());
+        assert!(call(&m, "writeFile", vec![path, Value::Int(1)]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn capabilities() {
+        let m = exports(Capabilities::none());
+        assert_eq!(
+            call(&m, "readFile", vec![Value::Str("x".to_string())]),
+            Err("readFile() needs file access, which is disabled".to_string())
+        );
+        assert!(call(&m, "env", vec![Value::Str("PATH".to_string())]).is_err());
+        assert!(call(&m, "exit", vec![Value::Int(0)]).is_err());
+
+        let m = exports(Capabilities {
+            env: true,
+            ..Capabilities::none()
+        });
+        assert_eq!(
+            call(&m, "env", vec![Value::Str("LOX_SURELY_UNSET".to_string())]),
+            Ok(Value::Nil)
+        );
+        assert!(call(&m, "exists", vec![Value::Str(".".to_string())]).is_err());
+    }
+}
@@ -0,0 +1,544 @@
+use super::super::map::OrderedMap;
+use super::super::methods::expect_args;
+use super::super::value::Value;
+use super::{add, string};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Deeper documents are rejected rather than risking a stack overflow.
+const MAX_DEPTH: usize = 512;
+
+pub fn exports() -> HashMap<String, Value> {
+    let mut exports = HashMap::new();
+
+    add(&mut exports, "parse", |args| {
+        let [text] = expect_args("parse", args)?;
+        parse(string("parse", &text)?)
+    });
+    // The optional indent is a number of spaces or the string to indent with.
+    // Without it, everything is written on one line.
+    add(&mut exports, "stringify", |args| {
+        let indent = match args.get(1) {
+            None | Some(Value::Nil) => None,
+            Some(Value::Int(n)) if (0..=10).contains(n) => Some(" ".repeat(*n as usize)),
+            Some(Value::Str(s)) => Some(s.clone()),
+            Some(v) => {
+                return Err(format!(
+                    "stringify() indent must be an int from 0 to 10 or a string, not {}",
+                    v.repr()
+                ))
+            }
+        };
+        match args.len() {
+            1 | 2 => stringify(&args[0], indent.as_deref()).map(Value::Str),
+            n => Err(format!(
+                "stringify() expects 1 or 2 arguments but got {}",
+                n
+            )),
+        }
+    });
+
+    exports
+}
+
+// Objects become maps, keeping the order of their keys. Integers that fit in
+// an int become ints, all other numbers floats.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut p = Parser {
+        chars: text.chars().collect(),
+        current: 0,
+        line: 1,
+        column: 1,
+        depth: 0,
+    };
+    p.skip_whitespace();
+    let value = p.value()?;
+    p.skip_whitespace();
+    match p.peek() {
+        None => Ok(value),
+        Some(c) => p.error(&format!("unexpected {:?} after the value", c)),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    current: usize,
+    // Position of the next character, for error messages.
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('{') => self.nested(Parser::object),
+            Some('[') => self.nested(Parser::array),
+            Some('"') => self.string().map(Value::Str),
+            Some('-') | Some('0'..='9') => self.number(),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('n') => self.literal("null", Value::Nil),
+            Some(c) => self.error(&format!("unexpected {:?}", c)),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn nested(&mut self, f: fn(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if self.depth == MAX_DEPTH {
+            return self.error("nested too deeply");
+        }
+        self.depth += 1;
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.advance();
+        let mut entries = OrderedMap::new();
+        self.skip_whitespace();
+        if self.eat('}') {
+            return Ok(Value::map(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return self.error("expected a string key");
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(':') {
+                return self.error("expected ':' after the key");
+            }
+            self.skip_whitespace();
+            let value = self.value()?;
+            entries.insert(Value::Str(key), value)?;
+
+            self.skip_whitespace();
+            if self.eat('}') {
+                return Ok(Value::map(entries));
+            }
+            if !self.eat(',') {
+                return self.error("expected ',' or '}'");
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.advance();
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(Value::list(items));
+        }
+
+        loop {
+            self.skip_whitespace();
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Value::list(items));
+            }
+            if !self.eat(',') {
+                return self.error("expected ',' or ']'");
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.advance();
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                None => return self.error("unterminated string"),
+                Some(c) if c < ' ' => return self.error("control character in string"),
+                Some(c) => c,
+            };
+            self.advance();
+            match c {
+                '"' => return Ok(s),
+                '\\' => s.push(self.escape()?),
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.advance();
+                return self.unicode();
+            }
+            Some(_) => return self.error("invalid escape"),
+            None => return self.error("unterminated string"),
+        };
+        self.advance();
+        Ok(c)
+    }
+
+    fn unicode(&mut self) -> Result<char, String> {
+        let high = self.hex()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).map_or_else(|| self.error("lone surrogate"), Ok);
+        }
+        // Characters outside the basic plane are written as a surrogate pair.
+        if !self.eat('\\') || !self.eat('u') {
+            return self.error("lone surrogate");
+        }
+        let low = self.hex()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return self.error("lone surrogate");
+        }
+        let c = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        Ok(char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut n = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => n = n * 16 + d,
+                None => return self.error("expected 4 hex digits after \\u"),
+            }
+            self.advance();
+        }
+        Ok(n)
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.current;
+        let (line, column) = (self.line, self.column);
+        let mut integral = true;
+
+        if self.peek() == Some('-') {
+            self.advance();
+        }
+        match self.peek() {
+            Some('0') => {
+                self.advance();
+            }
+            Some('1'..='9') => self.digits(),
+            _ => return self.error("expected a digit"),
+        }
+        if self.peek() == Some('.') {
+            integral = false;
+            self.advance();
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return self.error("expected a digit after '.'");
+            }
+            self.digits();
+        }
+        if let Some('e') | Some('E') = self.peek() {
+            integral = false;
+            self.advance();
+            if let Some('+') | Some('-') = self.peek() {
+                self.advance();
+            }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return self.error("expected a digit in the exponent");
+            }
+            self.digits();
+        }
+
+        let text: String = self.chars[start..self.current].iter().collect();
+        if integral {
+            if let Ok(i) = text.parse() {
+                return Ok(Value::Int(i));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+            _ => Err(format!(
+                "Invalid JSON at line {}, column {}: number {} is out of range",
+                line, column, text
+            )),
+        }
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        let (line, column) = (self.line, self.column);
+        for expected in word.chars() {
+            if self.advance() != Some(expected) {
+                return Err(format!(
+                    "Invalid JSON at line {}, column {}: expected {}",
+                    line, column, word
+                ));
+            }
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.peek() {
+            self.advance();
+        }
+    }
+
+    // Skips the next character if it is `c`.
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.advance();
+        }
+        matched
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.current += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    // An error at the next character.
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!(
+            "Invalid JSON at line {}, column {}: {}",
+            self.line, self.column, msg
+        ))
+    }
+}
+
+// Map keys are written as strings. Functions, modules, errors, non-finite
+// numbers and containers that hold themselves can't be represented.
+pub fn stringify(v: &Value, indent: Option<&str>) -> Result<String, String> {
+    let mut out = String::new();
+    write(&mut out, v, indent, 0, &mut Vec::new())?;
+    Ok(out)
+}
+
+fn write(
+    out: &mut String,
+    v: &Value,
+    indent: Option<&str>,
+    level: usize,
+    seen: &mut Vec<*const ()>,
+) -> Result<(), String> {
+    match v {
+        Value::Str(s) => write_string(out, s),
+        Value::Int(i) => out.push_str(&i.to_string()),
+        Value::Number(n) if n.is_finite() => out.push_str(&v.to_string()),
+        Value::Number(_) => return Err(format!("Can't convert {} to JSON", v)),
+        Value::Bool(b) => out.push_str(&b.to_string()),
+        Value::Nil => out.push_str("null"),
+        Value::List(items) => {
+            let id = Rc::as_ptr(items) as *const ();
+            enter(seen, id)?;
+            let items = items.borrow();
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                separate(out, i, indent, level + 1);
+                write(out, item, indent, level + 1, seen)?;
+            }
+            close(out, items.is_empty(), indent, level, ']');
+            seen.pop();
+        }
+        Value::Map(entries) => {
+            let id = Rc::as_ptr(entries) as *const ();
+            enter(seen, id)?;
+            let entries = entries.borrow();
+            out.push('{');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                separate(out, i, indent, level + 1);
+                write_string(out, &key.to_string());
+                out.push_str(if indent.is_some() { ": " } else { ":" });
+                write(out, value, indent, level + 1, seen)?;
+            }
+            close(out, entries.len() == 0, indent, level, '}');
+            seen.pop();
+        }
+        Value::Error(_) | Value::Module(_) | Value::Native(_) => {
+            return Err(format!("Can't convert {} to JSON", v.type_name()))
+        }
+    }
+    Ok(())
+}
+
+fn enter(seen: &mut Vec<*const ()>, id: *const ()) -> Result<(), String> {
+    if seen.contains(&id) {
+        return Err("Can't convert a value that contains itself to JSON".to_string());
+    }
+    if seen.len() == MAX_DEPTH {
+        return Err("Can't convert a value nested this deeply to JSON".to_string());
+    }
+    seen.push(id);
+    Ok(())
+}
+
+// Starts the `i`th element of a list or map.
+fn separate(out: &mut String, i: usize, indent: Option<&str>, level: usize) {
+    if i > 0 {
+        out.push(',');
+    }
+    newline(out, indent, level);
+}
+
+fn close(out: &mut String, empty: bool, indent: Option<&str>, level: usize, bracket: char) {
+    if !empty {
+        newline(out, indent, level);
+    }
+    out.push(bracket);
+}
+
+fn newline(out: &mut String, indent: Option<&str>, level: usize) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        out.push_str(&indent.repeat(level));
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) -> String {
+        stringify(&parse(text).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            round_trip(" {\"b\": [1, -2.5, 1e2, true, false, null], \"a\": {}} "),
+            "{\"b\":[1,-2.5,100,true,false,null],\"a\":{}}"
+        );
+        assert_eq!(parse("12"), Ok(Value::Int(12)));
+        assert_eq!(parse("12.0"), Ok(Value::Number(12.)));
+        assert_eq!(parse("99999999999999999999"), Ok(Value::Number(1e20)));
+        assert_eq!(
+            parse(r#""a\"\\\/\n\u00e9\ud83d\ude00""#),
+            Ok(Value::Str("a\"\\/\né😀".to_string()))
+        );
+        assert_eq!(round_trip("{\"a\": 1, \"a\": 2}"), "{\"a\":2}");
+
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(
+            error("{\"a\": 1,\n  \"b\" 2}"),
+            "Invalid JSON at line 2, column 7: expected ':' after the key"
+        );
+        assert_eq!(
+            error("[1, 2"),
+            "Invalid JSON at line 1, column 6: expected ',' or ']'"
+        );
+        assert_eq!(
+            error("[1 2]"),
+            "Invalid JSON at line 1, column 4: expected ',' or ']'"
+        );
+        assert_eq!(
+            error("{\"é\": tru}"),
+            "Invalid JSON at line 1, column 7: expected true"
+        );
+        assert_eq!(
+            error("1 x"),
+            "Invalid JSON at line 1, column 3: unexpected 'x' after the value"
+        );
+        assert_eq!(
+            error(""),
+            "Invalid JSON at line 1, column 1: unexpected end of input"
+        );
+        assert_eq!(
+            error("\"a\\q\""),
+            "Invalid JSON at line 1, column 4: invalid escape"
+        );
+        assert_eq!(
+            error("[\n\"a\nb\"]"),
+            "Invalid JSON at line 2, column 3: control character in string"
+        );
+        for bad in &[
+            "01",
+            "1.",
+            "-",
+            ".5",
+            "1e",
+            "[1,]",
+            "{,}",
+            "{1: 2}",
+            "\"\\x\"",
+            "\"\\ud83d\"",
+            "\"a\nb\"",
+            "\"abc",
+            "'a'",
+            "NaN",
+            "1e400",
+        ] {
+            assert!(parse(bad).is_err(), "{:?} parsed", bad);
+        }
+        assert!(parse(&"[".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn stringifying() {
+        let m = parse("{\"a\": [1, {\"b\": null}], \"c\": []}").unwrap();
+        assert_eq!(
+            stringify(&m, Some("  ")).unwrap(),
+            "{\n  \"a\": [\n    1,\n    {\n      \"b\": null\n    }\n  ],\n  \"c\": []\n}"
+        );
+
+        let mut map = OrderedMap::new();
+        map.insert(Value::Int(1), Value::Str("\u{1}\t".to_string()))
+            .unwrap();
+        map.insert(Value::Number(0.5), Value::Number(-0.0)).unwrap();
+        assert_eq!(
+            stringify(&Value::map(map), None).unwrap(),
+            "{\"1\":\"\\u0001\\t\",\"0.5\":-0}"
+        );
+
+        assert!(stringify(&Value::Number(f64::NAN), None).is_err());
+        assert!(stringify(&Value::native("f", |_| Ok(Value::Nil)), None).is_err());
+        let list = Value::list(vec![]);
+        if let Value::List(items) = &list {
+            items.borrow_mut().push(list.clone());
+        }
+        assert!(stringify(&list, None).is_err());
+        // Shared, but not cyclic.
+        let shared = Value::list(vec![]);
+        let twice = Value::list(vec![shared.clone(), shared]);
+        assert_eq!(stringify(&twice, None), Ok("[[],[]]".to_string()));
+
+        let mut deep = Value::list(vec![]);
+        for _ in 0..MAX_DEPTH {
+            deep = Value::list(vec![deep]);
+        }
+        assert!(stringify(&deep, None).is_err());
+    }
+}
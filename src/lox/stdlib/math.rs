@@ -0,0 +1,262 @@
+use super::super::methods::expect_args;
+use super::super::value::Value;
+use super::{add, int, number};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type Unary = fn(f64) -> f64;
+type Binary = fn(f64, f64) -> f64;
+
+pub fn exports() -> HashMap<String, Value> {
+    let mut exports = HashMap::new();
+    exports.insert("PI".to_string(), Value::Number(std::f64::consts::PI));
+    exports.insert("E".to_string(), Value::Number(std::f64::consts::E));
+
+    let unary: [(&str, Unary); 12] = [
+        ("sqrt", f64::sqrt),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        ("tan", f64::tan),
+        ("asin", f64::asin),
+        ("acos", f64::acos),
+        ("atan", f64::atan),
+        ("exp", f64::exp),
+        ("log", f64::ln),
+        ("log2", f64::log2),
+        ("log10", f64::log10),
+        ("cbrt", f64::cbrt),
+    ];
+    for &(name, f) in &unary {
+        add(&mut exports, name, move |args| {
+            let [x] = expect_args(name, args)?;
+            Ok(Value::Number(f(number(name, &x)?)))
+        });
+    }
+
+    let binary: [(&str, Binary); 2] = [("pow", f64::powf), ("atan2", f64::atan2)];
+    for &(name, f) in &binary {
+        add(&mut exports, name, move |args| {
+            let [x, y] = expect_args(name, args)?;
+            Ok(Value::Number(f(number(name, &x)?, number(name, &y)?)))
+        });
+    }
+
+    let rounding: [(&str, Unary); 3] = [
+        ("floor", f64::floor),
+        ("ceil", f64::ceil),
+        // Halfway cases round away from zero.
+        ("round", f64::round),
+    ];
+    for &(name, f) in &rounding {
+        add(&mut exports, name, move |args| {
+            let [x] = expect_args(name, args)?;
+            match x {
+                Value::Int(_) => Ok(x),
+                _ => to_int(name, f(number(name, &x)?)),
+            }
+        });
+    }
+
+    add(&mut exports, "abs", |args| {
+        let [x] = expect_args("abs", args)?;
+        match x {
+            Value::Int(i) => i
+                .checked_abs()
+                .map(Value::Int)
+                .ok_or_else(|| "Integer overflow in abs()".to_string()),
+            _ => Ok(Value::Number(number("abs", &x)?.abs())),
+        }
+    });
+    add(&mut exports, "min", |args| extreme("min", args, f64::lt));
+    add(&mut exports, "max", |args| extreme("max", args, f64::gt));
+
+    let rng = Rc::new(Rng::new(clock_seed()));
+    let r = rng.clone();
+    add(&mut exports, "seed", move |args| {
+        let [n] = expect_args("seed", args)?;
+        r.state.set(int("seed", &n)? as u64);
+        Ok(Value::Nil)
+    });
+    let r = rng.clone();
+    add(&mut exports, "random", move |args| {
+        let [] = expect_args("random", args)?;
+        Ok(Value::Number(r.float()))
+    });
+    add(&mut exports, "randint", move |args| {
+        let [a, b] = expect_args("randint", args)?;
+        let (a, b) = (int("randint", &a)?, int("randint", &b)?);
+        if a > b {
+            return Err(format!("randint() got an empty range {}..{}", a, b));
+        }
+        Ok(Value::Int(rng.between(a, b)))
+    });
+
+    exports
+}
+
+fn to_int(name: &str, n: f64) -> Result<Value, String> {
+    if n.is_finite() && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Ok(Value::Int(n as i64))
+    } else {
+        Err(format!("{}() result {} doesn't fit in an int", name, n))
+    }
+}
+
+// The smallest or largest of one or more numbers, returned unchanged.
+fn extreme(name: &str, args: Vec<Value>, better: fn(&f64, &f64) -> bool) -> Result<Value, String> {
+    let mut best: Option<(Value, f64)> = None;
+    for v in args {
+        let n = number(name, &v)?;
+        if best.as_ref().is_none_or(|(_, b)| better(&n, b)) {
+            best = Some((v, n));
+        }
+    }
+
+    best.map(|(v, _)| v)
+        .ok_or_else(|| format!("{}() expects at least 1 argument", name))
+}
+
+fn clock_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+// SplitMix64, small and good enough for simulations. The same seed always
+// gives the same sequence.
+struct Rng {
+    state: Cell<u64>,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng {
+            state: Cell::new(seed),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let state = self.state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        self.state.set(state);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), using the top 53 bits.
+    fn float(&self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [a, b]. Values from the incomplete last stretch of the u64
+    // range are drawn again so that no result is more likely than another.
+    fn between(&self, a: i64, b: i64) -> i64 {
+        let span = (b as u64).wrapping_sub(a as u64).wrapping_add(1);
+        if span == 0 {
+            return self.next() as i64;
+        }
+
+        let limit = u64::MAX - u64::MAX % span;
+        loop {
+            let n = self.next();
+            if n < limit {
+                return (a as u64).wrapping_add(n % span) as i64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(
+        exports: &HashMap<String, Value>,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, String> {
+        match &exports[name] {
+            Value::Native(f) => (f.function)(args),
+            v => panic!("{} is not a function: {}", name, v),
+        }
+    }
+
+    #[test]
+    fn functions() {
+        let m = exports();
+        assert_eq!(call(&m, "sqrt", vec![Value::Int(9)]), Ok(Value::Number(3.)));
+        assert_eq!(
+            call(&m, "pow", vec![Value::Int(2), Value::Number(0.5)]),
+            Ok(Value::Number(2f64.sqrt()))
+        );
+        assert_eq!(call(&m, "abs", vec![Value::Int(-3)]), Ok(Value::Int(3)));
+        assert_eq!(
+            call(&m, "abs", vec![Value::Number(-0.5)]),
+            Ok(Value::Number(0.5))
+        );
+        assert_eq!(
+            call(&m, "floor", vec![Value::Number(-1.5)]),
+            Ok(Value::Int(-2))
+        );
+        assert_eq!(
+            call(&m, "ceil", vec![Value::Number(1.2)]),
+            Ok(Value::Int(2))
+        );
+        assert_eq!(
+            call(&m, "round", vec![Value::Number(2.5)]),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            call(
+                &m,
+                "min",
+                vec![Value::Int(3), Value::Number(1.5), Value::Int(2)]
+            ),
+            Ok(Value::Number(1.5))
+        );
+        assert_eq!(
+            call(&m, "max", vec![Value::Int(3), Value::Number(3.)]),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(call(&m, "log", vec![m["E"].clone()]), Ok(Value::Number(1.)));
+        assert_eq!(call(&m, "cos", vec![Value::Int(0)]), Ok(Value::Number(1.)));
+
+        assert!(call(&m, "sqrt", vec![Value::Str("4".to_string())]).is_err());
+        assert!(call(&m, "sqrt", vec![]).is_err());
+        assert!(call(&m, "min", vec![]).is_err());
+        assert!(call(&m, "floor", vec![Value::Number(f64::NAN)]).is_err());
+        assert!(call(&m, "abs", vec![Value::Int(i64::MIN)]).is_err());
+    }
+
+    #[test]
+    fn random() {
+        let m = exports();
+        let draw = |m: &HashMap<String, Value>| -> Vec<Value> {
+            call(m, "seed", vec![Value::Int(42)]).unwrap();
+            (0..20)
+                .map(|_| call(m, "randint", vec![Value::Int(-2), Value::Int(2)]).unwrap())
+                .chain(Some(call(m, "random", vec![]).unwrap()))
+                .collect()
+        };
+
+        let first = draw(&m);
+        assert_eq!(first, draw(&m));
+        assert_eq!(first, draw(&exports()));
+        assert!(first[..20]
+            .iter()
+            .all(|v| matches!(v, Value::Int(i) if (-2..=2).contains(i))));
+        assert!(matches!(first[20], Value::Number(n) if (0.0..1.0).contains(&n)));
+
+        let full = call(
+            &m,
+            "randint",
+            vec![Value::Int(i64::MIN), Value::Int(i64::MAX)],
+        );
+        assert!(full.is_ok());
+        assert!(call(&m, "randint", vec![Value::Int(1), Value::Int(0)]).is_err());
+    }
+}
@@ -0,0 +1,62 @@
+mod builtins;
+mod io;
+pub mod json;
+mod math;
+mod time;
+
+use super::value::Value;
+use std::collections::HashMap;
+
+pub use io::Capabilities;
+pub use time::{Clock, ClockCell, SystemClock};
+
+// Exports of the built-in module `name`, which is imported like a file,
+// e.g. `import "math" as math`.
+pub fn module(name: &str, clock: &ClockCell) -> Option<HashMap<String, Value>> {
+    match name {
+        "math" => Some(math::exports()),
+        "json" => Some(json::exports()),
+        "time" => Some(time::exports(clock)),
+        _ => None,
+    }
+}
+
+// Functions defined in every global scope, like `str(x)`. Those that reach
+// outside the interpreter only work if `capabilities` allows it.
+pub fn globals(capabilities: Capabilities, clock: &ClockCell) -> HashMap<String, Value> {
+    let mut globals = builtins::exports();
+    globals.extend(io::exports(capabilities));
+    globals.extend(time::globals(clock));
+    globals
+}
+
+fn add(
+    exports: &mut HashMap<String, Value>,
+    name: &str,
+    function: impl Fn(Vec<Value>) -> Result<Value, String> + 'static,
+) {
+    exports.insert(name.to_string(), Value::native(name, function));
+}
+
+fn number(name: &str, v: &Value) -> Result<f64, String> {
+    v.as_f64()
+        .ok_or_else(|| format!("{}() expects numbers, not {}", name, v.type_name()))
+}
+
+fn int(name: &str, v: &Value) -> Result<i64, String> {
+    match v {
+        Value::Int(i) => Ok(*i),
+        _ => Err(format!("{}() expects ints, not {}", name, v.type_name())),
+    }
+}
+
+fn string<'v>(name: &str, v: &'v Value) -> Result<&'v str, String> {
+    match v {
+        Value::Str(s) => Ok(s),
+        _ => Err(format!(
+            "{}() expects a string, not {}",
+            name,
+            v.type_name()
+        )),
+    }
+}
@@ -0,0 +1,282 @@
+use super::super::methods::expect_args;
+use super::super::value::Value;
+use super::{add, number, string};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Where scripts get the time from. Embedders can swap in a fake one to make
+// scripts that depend on the time deterministic.
+pub trait Clock {
+    // Seconds since the Unix epoch.
+    fn now(&self) -> f64;
+    // Seconds since some fixed point, never going backwards.
+    fn monotonic(&self) -> f64;
+    fn sleep(&self, seconds: f64);
+}
+
+// Shared between the interpreter and the natives that read it, so swapping
+// the clock reaches functions that were handed out before.
+pub type ClockCell = Rc<RefCell<Rc<dyn Clock>>>;
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64())
+    }
+
+    fn monotonic(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    fn sleep(&self, seconds: f64) {
+        thread::sleep(Duration::from_secs_f64(seconds));
+    }
+}
+
+// The book's `clock()`, for timing code.
+pub fn globals(clock: &ClockCell) -> HashMap<String, Value> {
+    let mut exports = HashMap::new();
+    let c = clock.clone();
+    add(&mut exports, "clock", move |args| {
+        let [] = expect_args("clock", args)?;
+        Ok(Value::Number(c.borrow().monotonic()))
+    });
+    exports
+}
+
+pub fn exports(clock: &ClockCell) -> HashMap<String, Value> {
+    let mut exports = HashMap::new();
+
+    let c = clock.clone();
+    add(&mut exports, "now", move |args| {
+        let [] = expect_args("now", args)?;
+        Ok(Value::Number(c.borrow().now()))
+    });
+    let c = clock.clone();
+    add(&mut exports, "monotonic", move |args| {
+        let [] = expect_args("monotonic", args)?;
+        Ok(Value::Number(c.borrow().monotonic()))
+    });
+    let c = clock.clone();
+    add(&mut exports, "sleep", move |args| {
+        let [ms] = expect_args("sleep", args)?;
+        let ms = number("sleep", &ms)?;
+        if !(0.0..=1e12).contains(&ms) {
+            return Err(format!("sleep() can't wait {} ms", ms));
+        }
+        c.borrow().sleep(ms / 1000.0);
+        Ok(Value::Nil)
+    });
+    add(&mut exports, "format", |args| {
+        let [seconds] = expect_args("format", args)?;
+        format(number("format", &seconds)?).map(Value::Str)
+    });
+    add(&mut exports, "parse", |args| {
+        let [text] = expect_args("parse", args)?;
+        let text = string("parse", &text)?;
+        parse(text)
+            .map(Value::Number)
+            .ok_or_else(|| format!("Invalid ISO-8601 timestamp {:?}", text))
+    });
+
+    exports
+}
+
+const MS_PER_DAY: i64 = 86_400_000;
+
+// An ISO-8601 timestamp in UTC, with milliseconds only if there are any,
+// e.g. `2024-02-29T13:05:00Z`.
+fn format(seconds: f64) -> Result<String, String> {
+    // About 290,000 years either way, well past what the date math handles.
+    if !(-1e13..=1e13).contains(&seconds) {
+        return Err(format!("format() can't represent {} seconds", seconds));
+    }
+    let ms = (seconds * 1000.0).round() as i64;
+    let (year, month, day) = civil_from_days(ms.div_euclid(MS_PER_DAY));
+    let ms = ms.rem_euclid(MS_PER_DAY);
+    let (hour, minute, second) = (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60);
+
+    let mut s = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    );
+    if ms % 1000 != 0 {
+        s.push_str(&format!(".{:03}", ms % 1000));
+    }
+    s.push('Z');
+    Ok(s)
+}
+
+// Seconds since the epoch for `YYYY-MM-DD`, optionally followed by `T` and
+// `HH:MM`, seconds, a fraction and a `Z` or `±HH:MM` offset. Without an
+// offset the time is taken to be UTC.
+fn parse(text: &str) -> Option<f64> {
+    let mut s = Cursor { rest: text };
+    let year = s.digits(4)?;
+    s.expect('-')?;
+    let month = s.digits(2)?;
+    s.expect('-')?;
+    let day = s.digits(2)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut seconds = 0.0;
+    if s.expect('T').or_else(|| s.expect(' ')).is_some() {
+        let hour = s.digits(2)?;
+        s.expect(':')?;
+        let minute = s.digits(2)?;
+        let mut second = 0.0;
+        if s.expect(':').is_some() {
+            second = s.digits(2)? as f64;
+            if s.expect('.').is_some() {
+                let digits = s.rest.chars().take_while(char::is_ascii_digit).count();
+                if digits == 0 {
+                    return None;
+                }
+                second += format!("0.{}", &s.rest[..digits]).parse::<f64>().ok()?;
+                s.rest = &s.rest[digits..];
+            }
+        }
+        if hour > 23 || minute > 59 || second >= 60.0 {
+            return None;
+        }
+        seconds = (hour * 3600 + minute * 60) as f64 + second;
+
+        if s.expect('Z').is_none() && !s.rest.is_empty() {
+            let sign = match s.rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            s.rest = &s.rest[1..];
+            let hours = s.digits(2)?;
+            s.expect(':');
+            let minutes = s.digits(2)?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            seconds -= (sign * (hours * 3600 + minutes * 60)) as f64;
+        }
+    }
+
+    if !s.rest.is_empty() {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) as f64 * 86_400.0 + seconds)
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl Cursor<'_> {
+    fn digits(&mut self, n: usize) -> Option<i64> {
+        let digits = self.rest.get(..n)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        self.rest = &self.rest[n..];
+        digits.parse().ok()
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.rest = self.rest.strip_prefix(c)?;
+        Some(())
+    }
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, and
+// back. See http://howardhinnant.github.io/date_algorithms.html.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format(0.0), Ok("1970-01-01T00:00:00Z".to_string()));
+        assert_eq!(
+            format(951_782_400.25),
+            Ok("2000-02-29T00:00:00.250Z".to_string())
+        );
+        assert_eq!(format(-1.0), Ok("1969-12-31T23:59:59Z".to_string()));
+        assert!(format(f64::NAN).is_err());
+
+        assert_eq!(parse("1970-01-01"), Some(0.0));
+        assert_eq!(parse("2000-02-29T00:00:00.25Z"), Some(951_782_400.25));
+        assert_eq!(parse("2000-02-29 01:30+01:30"), Some(951_782_400.0));
+        assert_eq!(parse("2000-02-28T23:00:00-01:00"), Some(951_782_400.0));
+        for bad in &[
+            "",
+            "1970",
+            "1970-1-01",
+            "2001-02-29",
+            "2000-13-01",
+            "2000-01-01T24:00",
+            "2000-01-01T10",
+            "2000-01-01T10:00:00.",
+            "2000-01-01T10:00Zjunk",
+            "2000-01-01T10:00 +01:00",
+            "２０００-01-01",
+        ] {
+            assert_eq!(parse(bad), None, "{:?} parsed", bad);
+        }
+
+        for &days in &[-800_000, -1, 0, 59, 10_957, 2_932_896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+}
@@ -7,13 +7,22 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
     Plus,
     Semicolon,
+    Question,
+    Colon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     // One or two character tokens.
     Bang,
@@ -24,27 +33,45 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    StarStar,
+    LessLess,
+    GreaterGreater,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
 
     // Literals.
     Identifier,
     Str,
+    // Part of a string literal that is followed by an interpolated `${...}` expression.
+    Interpolation,
     Number,
 
     // Keywords.
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
+    Export,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -57,4 +84,33 @@ pub struct Token<'a> {
     pub lexeme: &'a str,
     pub literal: Option<Value>,
     pub line: usize,
+    // Whitespace and comments preceding the token, only filled in by a lossless scanner.
+    pub trivia: Vec<Trivia<'a>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    Comment,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia<'a> {
+    pub kind: TriviaKind,
+    pub text: &'a str,
+    pub line: usize,
+}
+
+// Rebuilds the original source from tokens produced by a lossless scanner.
+#[allow(dead_code)]
+pub fn source_text(tokens: &[Token]) -> String {
+    let mut s = String::new();
+    for t in tokens {
+        for trivia in &t.trivia {
+            s.push_str(trivia.text);
+        }
+        s.push_str(t.lexeme);
+    }
+    s
 }
@@ -1,5 +1,13 @@
+use super::map::OrderedMap;
+use super::module::Module;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt;
-use std::f64::{INFINITY, NEG_INFINITY};
+use std::rc::Rc;
+
+// Containers nested deeper than this are printed as `[...]` or `{...}`, rather
+// than risking a stack overflow.
+const MAX_PRINT_DEPTH: usize = 512;
 
 
 
@@ -8,48 +16,281 @@ use std::f64::{INFINITY, NEG_INFINITY};
 pub enum Value {
     Str(::std::string::String),
     Number(f64),
+    Int(i64),
     Bool(bool),
-    Nil
+    Nil,
+    // Lists are shared, so every copy of the value sees the same elements.
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<OrderedMap>>),
+    // A runtime error caught by a `catch` block.
+    Error(Rc<ErrorObject>),
+    Module(Rc<Module>),
+    Native(Rc<Native>),
+}
+
+// A function implemented in Rust, like the ones in built-in modules.
+pub struct Native {
+    pub name: String,
+    pub function: Box<dyn Fn(Vec<Value>) -> Result<Value, String>>,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ErrorObject {
+    pub message: String,
+    pub line: usize,
+    // Line the error was raised at, followed by the lines it was rethrown from.
+    pub trace: Vec<usize>,
 }
 use self::Value::*;
 
 impl Value {
     pub fn is_truthy(&self) -> bool {
         match *self {
-            Str(_) | Number(_) => true,
+            Str(_) | Number(_) | Int(_) | List(_) | Map(_) | Error(_) | Module(_) | Native(_) => true,
             Bool(b) => b,
             Nil => false,
         }
     }
-}
 
-impl PartialEq for Value {
-    fn eq(&self, other: &Value) -> bool {
+    // Ints are promoted to floats when mixed with Numbers.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Number(n) => Some(n),
+            Int(i) => Some(i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn list(items: Vec<Value>) -> Value {
+        List(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn map(entries: OrderedMap) -> Value {
+        Map(Rc::new(RefCell::new(entries)))
+    }
+
+    pub fn native(
+        name: &str,
+        function: impl Fn(Vec<Value>) -> Result<Value, String> + 'static,
+    ) -> Value {
+        Native(Rc::new(self::Native {
+            name: name.to_string(),
+            function: Box::new(function),
+        }))
+    }
+
+    pub fn error(message: String, line: usize, trace: Vec<usize>) -> Value {
+        Error(Rc::new(ErrorObject {
+            message,
+            line,
+            trace,
+        }))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Str(_) => "string",
+            Number(_) => "number",
+            Int(_) => "int",
+            Bool(_) => "bool",
+            Nil => "nil",
+            List(_) => "list",
+            Map(_) => "map",
+            Error(_) => "error",
+            Module(_) => "module",
+            Native(_) => "function",
+        }
+    }
+
+    // Containers that (indirectly) hold themselves are printed as `[...]` or `{...}` the second time.
+    fn fmt_nested(&self, f: &mut fmt::Formatter, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            List(items) => {
+                let id = Rc::as_ptr(items) as *const ();
+                if seen.len() == MAX_PRINT_DEPTH || seen.contains(&id) {
+                    return write!(f, "[...]");
+                }
+                seen.push(id);
+
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_element(f, seen)?;
+                }
+                seen.pop();
+                write!(f, "]")
+            }
+            Map(entries) => {
+                let id = Rc::as_ptr(entries) as *const ();
+                if seen.len() == MAX_PRINT_DEPTH || seen.contains(&id) {
+                    return write!(f, "{{...}}");
+                }
+                seen.push(id);
+
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    k.fmt_element(f, seen)?;
+                    write!(f, ": ")?;
+                    v.fmt_element(f, seen)?;
+                }
+                seen.pop();
+                write!(f, "}}")
+            }
+            _ => write!(f, "{}", self),
+        }
+    }
+
+    // The value as printed inside containers and error messages, with strings quoted.
+    pub fn repr(&self) -> String {
+        match self {
+            Str(s) => format!("{:?}", s),
+            _ => self.to_string(),
+        }
+    }
+
+    // Strings inside containers are quoted so `["1", 1]` stays readable.
+    fn fmt_element(&self, f: &mut fmt::Formatter, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Str(s) => write!(f, "{:?}", s),
+            _ => self.fmt_nested(f, seen),
+        }
+    }
+
+    // Compares containers with a stack of pairs still to compare rather than
+    // by recursing, so nesting depth doesn't use up the thread's stack.
+    // Containers that (indirectly) hold themselves would compare forever, so
+    // a pair met again counts as equal.
+    fn equals(&self, other: &Value) -> bool {
+        let mut pending = Vec::new();
+        if !self.shallow_equals(other, &mut pending) {
+            return false;
+        }
+        let mut seen = HashSet::new();
+        while let Some((a, b)) = pending.pop() {
+            let equal = match (&a, &b) {
+                (List(a), List(b)) => {
+                    if !seen.insert((Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ())) {
+                        continue;
+                    }
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter()
+                            .zip(b.iter())
+                            .all(|(x, y)| x.shallow_equals(y, &mut pending))
+                }
+                // Maps are equal when they hold equal values under the same keys, in any order.
+                (Map(a), Map(b)) => {
+                    if !seen.insert((Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ())) {
+                        continue;
+                    }
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter().all(|(k, v)| match b.get(k) {
+                            Ok(Some(w)) => v.shallow_equals(w, &mut pending),
+                            _ => false,
+                        })
+                }
+                _ => true,
+            };
+            if !equal {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Compares everything but the contents of containers, which are left in
+    // `pending` to compare.
+    fn shallow_equals(&self, other: &Value, pending: &mut Vec<(Value, Value)>) -> bool {
         match (self, other) {
-            (&Str(ref a), &Str(ref b)) => a == b,
+            (Str(a), Str(b)) => a == b,
             (&Number(a), &Number(b)) => a == b,
+            (&Int(a), &Int(b)) => a == b,
+            (&Int(a), &Number(b)) | (&Number(b), &Int(a)) => a as f64 == b,
             (&Bool(a), &Bool(b)) => a == b,
             (&Nil, &Nil) => true,
+            (List(a), List(b)) if Rc::ptr_eq(a, b) => true,
+            (Map(a), Map(b)) if Rc::ptr_eq(a, b) => true,
+            (List(_), List(_)) | (Map(_), Map(_)) => {
+                pending.push((self.clone(), other.clone()));
+                true
+            }
+            (Error(a), Error(b)) => Rc::ptr_eq(a, b),
+            (Module(a), Module(b)) => Rc::ptr_eq(a, b),
+            (Native(a), Native(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
+
+    // Moves out the elements of a list or map this is the last reference to,
+    // which would otherwise be dropped along with it.
+    fn take_contents(&mut self, into: &mut Vec<Value>) {
+        match self {
+            List(items) if Rc::strong_count(items) == 1 => {
+                if let Ok(mut items) = items.try_borrow_mut() {
+                    into.append(&mut items);
+                }
+            }
+            Map(entries) if Rc::strong_count(entries) == 1 => {
+                if let Ok(mut entries) = entries.try_borrow_mut() {
+                    for (k, v) in entries.drain() {
+                        into.push(k);
+                        into.push(v);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.equals(other)
+    }
+}
+
+// Dropping a deeply nested list would recurse once per level, so the contents
+// of containers being freed are dropped here in a loop instead.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_contents(&mut pending);
+        while let Some(mut v) = pending.pop() {
+            v.take_contents(&mut pending);
+        }
+    }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Str(ref s) => write!(f, "{}", s),
-            Number(n) => if n == INFINITY {
+            Number(n) => if n == f64::INFINITY {
                 write!(f, "Infinity")
-            } else if n == NEG_INFINITY {
+            } else if n == f64::NEG_INFINITY {
                 write!(f, "-Infinity")
-            } else if n == 0.0 && n.is_sign_negative() {
-                write!(f, "-{}", n)
             } else {
                 write!(f, "{}", n)
             },
+            Int(i) => write!(f, "{}", i),
             Bool(b) => write!(f, "{}", b),
-            Nil => write!(f, "nil")
+            Nil => write!(f, "nil"),
+            List(_) | Map(_) => self.fmt_nested(f, &mut Vec::new()),
+            Error(ref e) => write!(f, "Error: {}", e.message),
+            Module(ref m) => write!(f, "<module {}>", m.path.display()),
+            Native(ref n) => write!(f, "{:?}", n),
         }
     }
 }
\ No newline at end of file
@@ -1,36 +1,137 @@
-mod lox;
-
+use lox_rust::lox;
 use std::env;
 use std::fs;
-use std::io::{stdin, stdout, Write};
+use std::io::{stdin, stdout, BufReader, Read, Write};
+use std::path::Path;
+use std::process;
 
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
+    lox::with_stack(cli)
+}
+
+fn cli() -> Result<(), String> {
+    let mut args: Vec<String> = env::args().collect();
+
+    if args.len() > 1 && args[1] == "fmt" {
+        return fmt(&args[2..]);
+    }
+
+    // A language server for editors, speaking LSP over stdin and stdout.
+    if args.len() == 2 && args[1] == "lsp" {
+        let code = lox::lsp::serve(stdin().lock(), stdout().lock()).map_err(|e| e.to_string())?;
+        process::exit(code);
+    }
+
+    // A debug adapter for editors, speaking DAP over stdin and stdout.
+    if args.len() == 2 && args[1] == "dap" {
+        lox::dap::serve(BufReader::new(stdin()), stdout()).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    // --sandbox keeps scripts away from files, the environment and the process.
+    let mut capabilities = lox::Capabilities::all();
+    if args.len() > 1 && args[1] == "--sandbox" {
+        capabilities = lox::Capabilities::none();
+        args.remove(1);
+    }
 
     match args.len() {
-        1 => loop {
-            print!("> ");
-            let _ = stdout().flush();
+        1 => {
+            let mut session = lox::Session::new(capabilities);
+            loop {
+                print!("> ");
+                let _ = stdout().flush();
 
-            let mut s = String::new();
-            stdin().read_line(&mut s).expect("String is incorrect.");
+                let mut s = String::new();
+                stdin().read_line(&mut s).expect("String is incorrect.");
 
-            if s.trim() == "exit" {
-                break;
-            }
+                if s.trim() == "exit" {
+                    break;
+                }
 
-            if let Err(e) = lox::run(s) {
-                println!("{}", e);
+                if let Err(e) = session.run(&s) {
+                    println!("{}", e);
+                }
             }
-        },
+        }
         2 => {
-            let file = &args[1];
-            let content = fs::read_to_string(file).expect("Can't open script file.");
-            lox::run(content)?;
+            // Imports not found next to the importing file are looked up in LOX_PATH.
+            let search_path = env::var_os("LOX_PATH")
+                .map(|paths| env::split_paths(&paths).collect())
+                .unwrap_or_default();
+            if let Err(e) = lox::run_file(Path::new(&args[1]), search_path, capabilities) {
+                eprintln!("{}", e);
+                process::exit(e.exit_code());
+            }
         }
         _ => {
-            println!("Usage: lox-rust [script]")
+            eprintln!("Usage: lox-rust [--sandbox] [script]");
+            eprintln!("       lox-rust fmt [--check] [--width N] [files...]");
+            eprintln!("       lox-rust lsp");
+            eprintln!("       lox-rust dap");
+            process::exit(64);
+        }
+    }
+
+    Ok(())
+}
+
+// Formats files in place, or stdin to stdout when no files are given.
+// With --check nothing is written and the exit code is non-zero if any file would change.
+fn fmt(args: &[String]) -> Result<(), String> {
+    let mut check = false;
+    let mut width = lox::DEFAULT_WIDTH;
+    let mut files = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--width" => {
+                width = args
+                    .next()
+                    .and_then(|w| w.parse().ok())
+                    .ok_or("--width expects a number")?;
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        let mut source = String::new();
+        stdin()
+            .read_to_string(&mut source)
+            .map_err(|e| e.to_string())?;
+        let formatted = lox::format(&source, width)?;
+        if check {
+            return if formatted == source {
+                Ok(())
+            } else {
+                Err("<stdin> is not formatted".to_string())
+            };
+        }
+        print!("{}", formatted);
+        return Ok(());
+    }
+
+    let mut unformatted = 0;
+    for file in files {
+        let source = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        let formatted = lox::format(&source, width).map_err(|e| format!("{}: {}", file, e))?;
+        if formatted == source {
+            continue;
         }
+
+        if check {
+            println!("{} is not formatted", file);
+            unformatted += 1;
+        } else {
+            fs::write(file, formatted).map_err(|e| format!("{}: {}", file, e))?;
+        }
+    }
+
+    if unformatted > 0 {
+        return Err(format!("{} file(s) need formatting", unformatted));
     }
 
     Ok(())
@@ -0,0 +1,199 @@
+// Runs every `.lox` file under `tests/lox` through the CLI and checks its
+// output and exit code against annotations in the file, in the format of the
+// Crafting Interpreters test suite:
+//
+//   1 + 2; // expect: 3                      a line of stdout
+//   -"a"; // expect runtime error: ...       stderr, exit code 70
+//   1 +; // Error at ';': ...                a compile error, exit code 65
+//   // [line 3] Error at end: ...           one on another line
+//
+// Each top-level directory is a chapter; set LOX_TEST_FILTER to a part of a
+// path to only run matching files.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Default)]
+struct Expected {
+    stdout: Vec<String>,
+    // Message and line.
+    runtime_error: Option<(String, usize)>,
+    compile_errors: Vec<String>,
+}
+
+impl Expected {
+    fn parse(source: &str) -> Self {
+        let mut expected = Expected::default();
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            // The last annotation on the line, so `//` inside the code or the
+            // expected output doesn't start one.
+            let start = line.rfind("// expect").or_else(|| {
+                ["// Error", "// [line ", "// [java line "]
+                    .iter()
+                    .filter_map(|marker| line.rfind(marker))
+                    .max()
+            });
+            let comment = match start {
+                Some(start) => &line[start + 3..],
+                None => continue,
+            };
+
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expected.stdout.push(output.to_string());
+            } else if let Some(msg) = comment.strip_prefix("expect runtime error: ") {
+                expected.runtime_error = Some((msg.to_string(), line_number));
+            } else if comment.starts_with("Error") {
+                expected
+                    .compile_errors
+                    .push(format!("[line {}] {}", line_number, comment));
+            } else if let Some(rest) = comment
+                .strip_prefix("[line ")
+                .or_else(|| comment.strip_prefix("[java line "))
+            {
+                // The reference suite also has `[c line N]` for its bytecode
+                // interpreter, whose errors differ. Like jlox, this is a tree-walker.
+                expected.compile_errors.push(format!("[line {}", rest));
+            }
+        }
+        expected
+    }
+
+    fn exit_code(&self) -> i32 {
+        if !self.compile_errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+}
+
+// What went wrong running `path`, if anything.
+fn check(path: &Path) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let expected = Expected::parse(&source);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| e.to_string())?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout: Vec<&str> = stdout.lines().collect();
+    let stderr: Vec<&str> = stderr.lines().collect();
+
+    let mut problems = Vec::new();
+    if stdout != expected.stdout {
+        problems.push(format!(
+            "expected output\n{}\ngot\n{}",
+            indent(&expected.stdout),
+            indent(&stdout)
+        ));
+    }
+
+    if !expected.compile_errors.is_empty() {
+        if stderr != expected.compile_errors {
+            problems.push(format!(
+                "expected compile errors\n{}\ngot\n{}",
+                indent(&expected.compile_errors),
+                indent(&stderr)
+            ));
+        }
+    } else {
+        match &expected.runtime_error {
+            Some((msg, line)) => {
+                let wanted = [msg.clone(), format!("[line {}]", line)];
+                if stderr.len() < 2 || stderr[..2] != wanted {
+                    problems.push(format!(
+                        "expected runtime error\n{}\ngot\n{}",
+                        indent(&wanted),
+                        indent(&stderr)
+                    ));
+                }
+            }
+            None if !stderr.is_empty() => {
+                problems.push(format!("unexpected errors\n{}", indent(&stderr)));
+            }
+            None => {}
+        }
+    }
+
+    let code = output.status.code().unwrap_or(-1);
+    if code != expected.exit_code() {
+        problems.push(format!(
+            "expected exit code {}, got {}",
+            expected.exit_code(),
+            code
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+fn indent<S: AsRef<str>>(lines: &[S]) -> String {
+    lines
+        .iter()
+        .map(|l| format!("    {}", l.as_ref()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let mut files = Vec::new();
+    lox_files(&root, &mut files);
+    if let Ok(filter) = env::var("LOX_TEST_FILTER") {
+        files.retain(|f| f.to_string_lossy().contains(&filter));
+    }
+
+    // Passed and total files per chapter.
+    let mut chapters: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut failures = Vec::new();
+    for file in &files {
+        let relative = file.strip_prefix(&root).unwrap();
+        let chapter = relative.iter().next().unwrap().to_string_lossy();
+        let counts = chapters.entry(chapter.into_owned()).or_default();
+        counts.1 += 1;
+        match check(file) {
+            Ok(()) => counts.0 += 1,
+            Err(problems) => failures.push(format!("{}:\n{}", relative.display(), problems)),
+        }
+    }
+
+    for (chapter, (passed, total)) in &chapters {
+        println!("{:<16} {}/{}", chapter, passed, total);
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} files failed\n\n{}",
+        failures.len(),
+        files.len(),
+        failures.join("\n\n")
+    );
+}
@@ -0,0 +1,282 @@
+// Drives `lox-rust dap` through a debugging session over its stdio, as an
+// editor would. Requests that need the script paused wait for it to stop.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAIN: &str = "var a = 1;
+import \"lib.lox\" as l;
+var b = [a, l.y];
+b;
+";
+
+const LIB: &str = "var x = 10;
+export var y = x * 2;
+";
+
+// Catches every error the script raises, so only the debugger can stop it.
+const LOOP: &str = "var i = 0;
+while (true) {
+  try {
+    var _ = i += 1;
+  } catch (e) {
+    e;
+  }
+}
+";
+
+struct Client {
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    seq: usize,
+}
+
+impl Client {
+    fn send(&mut self, command: &str, arguments: &str) {
+        self.seq += 1;
+        let body = format!(
+            r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+            self.seq, command, arguments
+        );
+        write!(self.input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.input.flush().unwrap();
+    }
+
+    fn read(&mut self) -> String {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.output.read_line(&mut line).unwrap();
+            match line.trim_end().strip_prefix("Content-Length: ") {
+                Some(n) => length = n.parse().unwrap(),
+                None if line.trim_end().is_empty() => break,
+                None => panic!("unexpected header {:?}", line),
+            }
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    // Reads messages up to the first that contains `part`.
+    fn until(&mut self, part: &str) -> String {
+        loop {
+            let message = self.read();
+            if message.contains(part) {
+                return message;
+            }
+        }
+    }
+
+    // Sends a request and returns its response.
+    fn request(&mut self, command: &str, arguments: &str) -> String {
+        self.send(command, arguments);
+        self.until(&format!(r#""request_seq":{},"#, self.seq))
+    }
+}
+
+// Starts the adapter and launches `program` with breakpoints on `lines`.
+fn launch(program: &Path, lines: &[usize]) -> (Child, Client) {
+    let mut adapter = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut client = Client {
+        input: adapter.stdin.take().unwrap(),
+        output: BufReader::new(adapter.stdout.take().unwrap()),
+        seq: 0,
+    };
+
+    client.request("initialize", r#"{"adapterID":"lox"}"#);
+    client.until(r#""event":"initialized""#);
+    let program = program.display().to_string();
+    client.request("launch", &format!(r#"{{"program":{:?}}}"#, program));
+    let breakpoints: Vec<String> = lines
+        .iter()
+        .map(|line| format!(r#"{{"line":{}}}"#, line))
+        .collect();
+    client.request(
+        "setBreakpoints",
+        &format!(
+            r#"{{"source":{{"path":{:?}}},"breakpoints":[{}]}}"#,
+            program,
+            breakpoints.join(",")
+        ),
+    );
+    client.send("configurationDone", "{}");
+    (adapter, client)
+}
+
+fn expect(message: &str, parts: &[&str]) {
+    for part in parts {
+        assert!(message.contains(part), "{} not in {}", part, message);
+    }
+}
+
+#[test]
+fn session() {
+    let dir = std::env::temp_dir().join(format!("lox-dap-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.lox"), MAIN).unwrap();
+    fs::write(dir.join("lib.lox"), LIB).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let main = dir.join("main.lox").display().to_string();
+    let lib = dir.join("lib.lox").display().to_string();
+
+    let mut adapter = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut client = Client {
+        input: adapter.stdin.take().unwrap(),
+        output: BufReader::new(adapter.stdout.take().unwrap()),
+        seq: 0,
+    };
+
+    let response = client.request("initialize", r#"{"adapterID":"lox"}"#);
+    expect(&response, &[r#""supportsConfigurationDoneRequest":true"#]);
+    client.until(r#""event":"initialized""#);
+    let launch = format!(r#"{{"program":{:?},"stopOnEntry":true}}"#, main);
+    expect(&client.request("launch", &launch), &[r#""success":true"#]);
+    let breakpoints = format!(
+        r#"{{"source":{{"path":{:?}}},"breakpoints":[{{"line":2}}]}}"#,
+        lib
+    );
+    expect(
+        &client.request("setBreakpoints", &breakpoints),
+        &[r#""breakpoints":[{"verified":true,"line":2}]"#],
+    );
+    expect(
+        &client.request("stackTrace", r#"{"threadId":1}"#),
+        &[r#""success":false"#, "The script isn't paused"],
+    );
+
+    client.send("configurationDone", "{}");
+    expect(
+        &client.until(r#""event":"stopped""#),
+        &[r#""reason":"entry""#],
+    );
+    expect(
+        &client.request("threads", "{}"),
+        &[r#""threads":[{"id":1,"name":"main"}]"#],
+    );
+
+    // Stops inside the imported module, with the importer's frame below it.
+    client.send("continue", r#"{"threadId":1}"#);
+    expect(
+        &client.until(r#""event":"stopped""#),
+        &[r#""reason":"breakpoint""#],
+    );
+    expect(
+        &client.request("stackTrace", r#"{"threadId":1}"#),
+        &[
+            r#""totalFrames":2"#,
+            r#"{"id":2,"name":"lib.lox","line":2"#,
+            r#"{"id":1,"name":"main.lox","line":2"#,
+            &format!(r#""path":{:?}"#, lib),
+        ],
+    );
+    expect(
+        &client.request("scopes", r#"{"frameId":2}"#),
+        &[
+            r#"{"name":"Locals","variablesReference":1"#,
+            r#"{"name":"Globals","variablesReference":2"#,
+        ],
+    );
+    expect(
+        &client.request("variables", r#"{"variablesReference":2}"#),
+        &[r#""variables":[{"name":"x","value":"10","type":"int","variablesReference":0}]"#],
+    );
+    expect(
+        &client.request("evaluate", r#"{"expression":"x + 1","frameId":2}"#),
+        &[r#""result":"11""#],
+    );
+    expect(
+        &client.request("evaluate", r#"{"expression":"a","frameId":1}"#),
+        &[r#""result":"1""#],
+    );
+    expect(
+        &client.request("evaluate", r#"{"expression":"nope"}"#),
+        &[r#""success":false"#, "Undefined variable"],
+    );
+
+    client.send("stepOut", r#"{"threadId":1}"#);
+    expect(
+        &client.until(r#""event":"stopped""#),
+        &[r#""reason":"step""#],
+    );
+    expect(
+        &client.request("stackTrace", r#"{"threadId":1}"#),
+        &[
+            r#""totalFrames":1"#,
+            r#"{"id":1,"name":"main.lox","line":3"#,
+        ],
+    );
+
+    client.send("next", r#"{"threadId":1}"#);
+    client.until(r#""event":"stopped""#);
+    client.request("scopes", r#"{"frameId":1}"#);
+    expect(
+        &client.request("variables", r#"{"variablesReference":2}"#),
+        &[
+            r#"{"name":"b","value":"[1, 20]","type":"list","variablesReference":3}"#,
+            r#"{"name":"l","value":"<module "#,
+        ],
+    );
+    expect(
+        &client.request("variables", r#"{"variablesReference":3}"#),
+        &[r#"{"name":"1","value":"20","type":"int","variablesReference":0}"#],
+    );
+
+    client.send("continue", r#"{"threadId":1}"#);
+    expect(
+        &client.until(r#""event":"output""#),
+        &[r#""category":"stdout","output":"[1, 20]\n""#],
+    );
+    expect(&client.until(r#""event":"exited""#), &[r#""exitCode":0"#]);
+    client.until(r#""event":"terminated""#);
+    client.request("disconnect", "{}");
+    drop(client);
+    assert!(adapter.wait().unwrap().success());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disconnect_inside_try() {
+    let dir = std::env::temp_dir().join(format!("lox-dap-try-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let program = dir.canonicalize().unwrap().join("loop.lox");
+    fs::write(&program, LOOP).unwrap();
+
+    let (mut adapter, mut client) = launch(&program, &[4]);
+    expect(
+        &client.until(r#""event":"stopped""#),
+        &[r#""reason":"breakpoint""#],
+    );
+    client.request("disconnect", "{}");
+
+    // Neither the catch block nor the breakpoint may keep the script going.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while adapter.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            adapter.kill().unwrap();
+            panic!("the adapter kept running after the client disconnected");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let mut rest = String::new();
+    client.output.read_to_string(&mut rest).unwrap();
+    assert!(!rest.contains("Stopped by the debugger"), "{}", rest);
+    assert!(!rest.contains(r#""event":"stopped""#), "{}", rest);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,200 @@
+// Drives `lox-rust lsp` through a scripted editor session over its stdio and
+// checks the responses, as an editor would see them.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const URI: &str = "file:///session.lox";
+
+const SOURCE: &str = "var total = 0;
+for (var i in [1, 2]) {
+  total += i;
+}
+str(total);
+";
+
+fn message(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    message(&format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+        id, method, params
+    ))
+}
+
+fn notification(method: &str, params: &str) -> String {
+    message(&format!(
+        r#"{{"jsonrpc":"2.0","method":"{}","params":{}}}"#,
+        method, params
+    ))
+}
+
+// Params naming the document and a position in it.
+fn at(line: usize, character: usize) -> String {
+    format!(
+        r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+        URI, line, character
+    )
+}
+
+fn with(params: String, extra: &str) -> String {
+    format!("{},{}}}", &params[..params.len() - 1], extra)
+}
+
+fn range(line: usize, start: usize, end: usize) -> String {
+    format!(
+        r#""range":{{"start":{{"line":{},"character":{}}},"end":{{"line":{},"character":{}}}}}"#,
+        line, start, line, end
+    )
+}
+
+// Splits the server's output into message bodies.
+fn messages(mut output: &str) -> Vec<&str> {
+    let mut bodies = Vec::new();
+    while let Some(rest) = output.strip_prefix("Content-Length: ") {
+        let (length, rest) = rest.split_at(rest.find("\r\n\r\n").unwrap());
+        let rest = &rest[4..];
+        let (body, rest) = rest.split_at(length.parse().unwrap());
+        bodies.push(body);
+        output = rest;
+    }
+    assert_eq!(output, "", "trailing output");
+    bodies
+}
+
+#[test]
+fn session() {
+    let open = format!(
+        r#"{{"textDocument":{{"uri":"{}","languageId":"lox","version":1,"text":{:?}}}}}"#,
+        URI, SOURCE
+    );
+    let broken = format!(
+        r#"{{"textDocument":{{"uri":"{}","version":2}},"contentChanges":[{{"text":"var = 1;"}}]}}"#,
+        URI
+    );
+    let script = [
+        request(1, "initialize", r#"{"capabilities":{}}"#),
+        notification("initialized", "{}"),
+        notification("textDocument/didOpen", &open),
+        request(2, "textDocument/definition", &at(2, 3)),
+        request(
+            3,
+            "textDocument/references",
+            &with(at(0, 5), r#""context":{"includeDeclaration":true}"#),
+        ),
+        request(4, "textDocument/hover", &at(4, 1)),
+        request(5, "textDocument/hover", &at(2, 3)),
+        request(
+            6,
+            "textDocument/documentSymbol",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        ),
+        request(7, "textDocument/completion", &at(2, 2)),
+        request(
+            8,
+            "textDocument/rename",
+            &with(at(1, 9), r#""newName":"n""#),
+        ),
+        request(
+            9,
+            "textDocument/rename",
+            &with(at(1, 9), r#""newName":"1x""#),
+        ),
+        notification("textDocument/didChange", &broken),
+        request(10, "textDocument/formatting", "{}"),
+        request(11, "shutdown", "null"),
+        notification("exit", "null"),
+    ];
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    server
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.concat().as_bytes())
+        .unwrap();
+    let output = server.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = String::from_utf8(output.stdout).unwrap();
+    let messages = messages(&output);
+    let response = |id: usize| {
+        let prefix = format!(r#"{{"jsonrpc":"2.0","id":{},"#, id);
+        let found = messages.iter().find(|m| m.starts_with(&prefix));
+        found
+            .unwrap_or_else(|| panic!("no response to {}", id))
+            .to_string()
+    };
+    let expect = |id: usize, parts: &[&str]| {
+        let response = response(id);
+        for part in parts {
+            assert!(response.contains(part), "{} not in {}", part, response);
+        }
+    };
+
+    expect(
+        1,
+        &[r#""definitionProvider":true"#, r#""textDocumentSync":1"#],
+    );
+    assert_eq!(
+        messages[1],
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{{"uri":"{}","diagnostics":[]}}}}"#,
+            URI
+        )
+    );
+    expect(
+        2,
+        &[&format!(
+            r#""result":{{"uri":"{}",{}}}"#,
+            URI,
+            range(0, 4, 9)
+        )],
+    );
+    expect(3, &[&range(0, 4, 9), &range(2, 2, 7), &range(4, 4, 9)]);
+    expect(4, &["Built-in function `str`", &range(4, 0, 3)]);
+    expect(
+        5,
+        &[r#""value":"```lox\nvar total = 0;\n```""#, &range(2, 2, 7)],
+    );
+    expect(
+        6,
+        &[r#""name":"total","kind":13"#, r#""name":"i","kind":13"#],
+    );
+    expect(
+        7,
+        &[
+            r#""label":"i""#,
+            r#""label":"total""#,
+            r#""label":"str""#,
+            r#""label":"while""#,
+        ],
+    );
+    expect(
+        8,
+        &[
+            &format!(
+                r#"{{"changes":{{"{}":[{{{},"newText":"n"}}"#,
+                URI,
+                range(1, 9, 10)
+            ),
+            &range(2, 11, 12),
+        ],
+    );
+    expect(
+        9,
+        &[r#""error":{"code":-32602,"message":"'1x' is not a valid name"}"#],
+    );
+    assert!(messages
+        .iter()
+        .any(|m| m.contains(r#""message":"Expect variable name.""#)));
+    expect(10, &[r#""code":-32601"#]);
+    expect(11, &[r#""result":null"#]);
+}
@@ -0,0 +1,26 @@
+// Types lines into the REPL of `lox-rust` and checks what it prints back.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn keeps_state_between_lines() {
+    let mut repl = Command::new(env!("CARGO_BIN_EXE_lox-rust"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let lines = "var a = 1\na += 1\nimport \"math\" as m\nm.floor(a / 2.0)\nnope\na\nexit\n";
+    repl.stdin
+        .take()
+        .unwrap()
+        .write_all(lines.as_bytes())
+        .unwrap();
+
+    let output = repl.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "> > 2\n> > 1\n> Undefined variable 'nope'\n[line 1]\n> 2\n> "
+    );
+}